@@ -0,0 +1,993 @@
+//! Generates a C header for a crate exporting functions through cursed, for use from `build.rs`.
+//!
+//! ```no_run
+//! cursed_header::Builder::new("src/lib.rs")
+//!     .include_guard("MY_CRATE_H")
+//!     .generate()
+//!     .expect("header generated")
+//!     .write_to_file("include/my_crate.h")
+//!     .expect("header written");
+//! ```
+//!
+//! The crate root is parsed along with every `mod` declared in it, and the header contains every
+//! `#[no_mangle] extern "C"` function, every `#[export]` shim, the output of the macros listed in
+//! `GENERATORS` such as `generate_vec_ffi!`, `#[no_mangle]` statics, `#[repr(C)]` structs and enums, and type aliases of `extern "C" fn`
+//! pointers. Each `Callback<A, R>`, `InSlice<T>` and `OutSlice<T>` used is declared as a struct
+//! named after its type arguments, and any other type that appears in a signature is declared as
+//! an opaque struct.
+
+mod ty;
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use syn::ext::IdentExt;
+use syn::{Attribute, Fields, FnArg, Item, Lit, Meta, NestedMeta, Pat, ReturnType, Signature};
+
+pub use crate::ty::CType;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, syn::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Builder {
+    root: PathBuf,
+    include_guard: String,
+}
+
+impl Builder {
+    /// Creates a builder for the crate whose root module (usually `src/lib.rs`) is at `root`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Builder {
+        Builder {
+            root: root.into(),
+            include_guard: "CURSED_GENERATED_H".into(),
+        }
+    }
+
+    pub fn include_guard<S: Into<String>>(mut self, guard: S) -> Builder {
+        self.include_guard = guard.into();
+        self
+    }
+
+    pub fn generate(self) -> Result<Header, Error> {
+        let mut header = Header {
+            include_guard: self.include_guard,
+            ..Default::default()
+        };
+        let dir = self
+            .root
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_owned();
+        header.parse_file(&self.root, &dir)?;
+        header.resolve_optionals();
+        Ok(header)
+    }
+}
+
+type Params = Vec<(String, CType)>;
+
+struct Function {
+    docs: Vec<String>,
+    ret: CType,
+    name: String,
+    params: Params,
+}
+
+struct Struct {
+    docs: Vec<String>,
+    name: String,
+    fields: Vec<(String, CType)>,
+}
+
+struct Enum {
+    docs: Vec<String>,
+    name: String,
+    repr: String,
+    variants: Vec<(String, String)>,
+}
+
+struct Typedef {
+    docs: Vec<String>,
+    name: String,
+    ty: CType,
+}
+
+struct Static {
+    docs: Vec<String>,
+    name: String,
+    ty: CType,
+    /// A value to also `#define` as `{name}_ID`, for use in constant expressions.
+    id: Option<u64>,
+}
+
+/// A macro exported by cursed that generates FFI functions for the types it is invoked with, as
+/// `TAG_NAME => Type, ...`, with one type per entry for each of `types`.
+struct Generator {
+    name: &'static str,
+    types: &'static [&'static str],
+    /// The docs and signature of each function it generates.
+    functions: &'static [(&'static str, &'static str)],
+}
+
+/// Every macro that generates exports. Each must list every function the macro emits, as the
+/// header cannot expand macros from another crate.
+const GENERATORS: &[Generator] = &[
+    Generator {
+        name: "generate_vec_ffi",
+        types: &["element"],
+        functions: &[
+            (
+                "A constructor for `Vec` for the C FFI, accepting types provided from generated constants.",
+                "fn vec_new(ty: TypeTag) -> Nullable<RawVec>",
+            ),
+            (
+                "A function to free vectors.",
+                "fn vec_free(handle: *mut RawVec, ty: TypeTag, exception: OutPtr<Exception>)",
+            ),
+            (
+                "Inserts a copy of `*value` at `index`, which may be the length to append.",
+                "fn vec_insert(handle: InOut<RawVec>, ty: TypeTag, index: u64, value: InRaw, exception: OutPtr<Exception>)",
+            ),
+            (
+                "Replaces the value at `index` with a copy of `*value`, dropping the old value.",
+                "fn vec_set(handle: InOut<RawVec>, ty: TypeTag, index: u64, value: InRaw, exception: OutPtr<Exception>)",
+            ),
+            ("", "fn vec_debug_print(handle: *const RawVec, ty: TypeTag)"),
+        ],
+    },
+    Generator {
+        name: "generate_map_ffi",
+        types: &["key", "value"],
+        functions: &[
+            ("", "fn map_new(ty: TypeTag) -> Nullable<RawMap>"),
+            (
+                "",
+                "fn map_free(handle: Nullable<RawMap>, ty: TypeTag, exception: OutPtr<Exception>)",
+            ),
+            (
+                "Inserts copies of `*key` and `*value`, returning whether a value was replaced.",
+                "fn map_insert(handle: In<RawMap>, ty: TypeTag, key: InRaw, value: InRaw, exception: OutPtr<Exception>) -> bool",
+            ),
+            (
+                "Returns a new reference to the value for `*key`, or null if there is none.",
+                "fn map_get(handle: In<RawMap>, ty: TypeTag, key: InRaw, exception: OutPtr<Exception>) -> ArcPtr<c_void>",
+            ),
+            (
+                "Removes the value for `*key`, returning it, or null if there was none.",
+                "fn map_remove(handle: In<RawMap>, ty: TypeTag, key: InRaw, exception: OutPtr<Exception>) -> ArcPtr<c_void>",
+            ),
+            (
+                "Returns the keys as a new `Vec`, to be freed with `vec_free` and the key type's tag.",
+                "fn map_keys(handle: In<RawMap>, ty: TypeTag, exception: OutPtr<Exception>) -> Nullable<RawVec>",
+            ),
+            (
+                "Creates an iterator over a snapshot of the entries, taking the map's lock once. Free with `map_iter_free`.",
+                "fn map_iter_new(handle: In<RawMap>, ty: TypeTag, exception: OutPtr<Exception>) -> Nullable<RawMapIter>",
+            ),
+        ],
+    },
+];
+
+/// The FNV-1a hash used by `cursed::tag::TypeTag::from_name`.
+fn type_tag(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// A generated header. Use `to_string()` to get its contents.
+#[derive(Default)]
+pub struct Header {
+    include_guard: String,
+    functions: Vec<Function>,
+    structs: Vec<Struct>,
+    enums: Vec<Enum>,
+    typedefs: Vec<Typedef>,
+    statics: Vec<Static>,
+    skipped: Vec<String>,
+}
+
+fn docs(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(meta)) if meta.path.is_ident("doc") => match meta.lit {
+                Lit::Str(s) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn has_attr(attrs: &[Attribute], name: &str) -> bool {
+    attrs.iter().any(|attr| {
+        attr.path
+            .segments
+            .last()
+            .map(|x| x.ident == name)
+            .unwrap_or(false)
+    })
+}
+
+/// Returns the `repr` hints on an item, e.g. `["C"]` or `["u32"]`.
+fn reprs(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::List(list)) if list.path.is_ident("repr") => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|x| match x {
+            NestedMeta::Meta(meta) => meta.path().get_ident().map(|x| x.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_cfg_test(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        attr.path.is_ident("cfg")
+            && attr
+                .tokens
+                .to_string()
+                .replace(' ', "")
+                .starts_with("(test")
+    })
+}
+
+fn is_extern_c(sig: &Signature) -> bool {
+    match &sig.abi {
+        Some(abi) => abi.name.as_ref().map(|x| x.value() == "C").unwrap_or(true),
+        None => false,
+    }
+}
+
+fn param_name(arg: &FnArg, index: usize) -> String {
+    match arg {
+        FnArg::Typed(pat) => match &*pat.pat {
+            Pat::Ident(ident) => ident.ident.to_string(),
+            _ => format!("arg{}", index),
+        },
+        FnArg::Receiver(_) => "self".into(),
+    }
+}
+
+fn screaming_snake(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+impl Header {
+    fn parse_file(&mut self, path: &Path, mod_dir: &Path) -> Result<(), Error> {
+        let source = fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        let file = syn::parse_file(&source).map_err(|e| Error::Parse(path.to_owned(), e))?;
+        self.parse_items(&file.items, mod_dir)
+    }
+
+    fn parse_items(&mut self, items: &[Item], mod_dir: &Path) -> Result<(), Error> {
+        for item in items {
+            match item {
+                Item::Mod(item) if !is_cfg_test(&item.attrs) => {
+                    let name = item.ident.to_string();
+                    let child_dir = mod_dir.join(&name);
+                    match &item.content {
+                        Some((_, items)) => self.parse_items(items, &child_dir)?,
+                        None => {
+                            let file = mod_dir.join(format!("{}.rs", name));
+                            if file.exists() {
+                                self.parse_file(&file, &child_dir)?;
+                            } else {
+                                self.parse_file(&child_dir.join("mod.rs"), &child_dir)?;
+                            }
+                        }
+                    }
+                }
+                Item::Fn(item) if is_cfg_test(&item.attrs) => {}
+                Item::Fn(item) if has_attr(&item.attrs, "export") => {
+                    self.add_export(docs(&item.attrs), &item.sig)
+                }
+                Item::Fn(item) if has_attr(&item.attrs, "no_mangle") && is_extern_c(&item.sig) => {
+                    self.add_function(docs(&item.attrs), &item.sig)
+                }
+                Item::Static(item) if has_attr(&item.attrs, "no_mangle") => {
+                    match ty::map(&item.ty) {
+                        Ok(ty) => self.statics.push(Static {
+                            docs: docs(&item.attrs),
+                            name: item.ident.to_string(),
+                            ty,
+                            id: None,
+                        }),
+                        Err(e) => self.skipped.push(format!("static {}: {}", item.ident, e)),
+                    }
+                }
+                Item::Struct(item) if reprs(&item.attrs).iter().any(|x| x == "C") => {
+                    self.add_struct(item)
+                }
+                Item::Enum(item) => self.add_enum(item),
+                Item::Type(item) if matches!(&*item.ty, syn::Type::BareFn(_)) => {
+                    match ty::map(&item.ty) {
+                        Ok(ty) => self.typedefs.push(Typedef {
+                            docs: docs(&item.attrs),
+                            name: item.ident.to_string(),
+                            ty,
+                        }),
+                        Err(e) => self.skipped.push(format!("type {}: {}", item.ident, e)),
+                    }
+                }
+                Item::Macro(item) if item.ident.is_none() => {
+                    let name = item.mac.path.segments.last().map(|x| x.ident.to_string());
+                    let generator = GENERATORS
+                        .iter()
+                        .find(|x| name.as_ref().map(|name| name == x.name).unwrap_or(false));
+                    if let Some(generator) = generator {
+                        self.add_generated(generator, &item.mac.tokens);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn map_signature(&self, sig: &Signature) -> Result<(CType, Params), (String, String)> {
+        let ret = match &sig.output {
+            ReturnType::Default => CType::Void,
+            ReturnType::Type(_, ty) => ty::map(ty).map_err(|e| ("return type".into(), e))?,
+        };
+        let mut params = vec![];
+        for (i, arg) in sig.inputs.iter().enumerate() {
+            let name = param_name(arg, i);
+            let ty = match arg {
+                FnArg::Typed(pat) => ty::map(&pat.ty).map_err(|e| (name.clone(), e))?,
+                FnArg::Receiver(_) => return Err((name, "methods cannot be exported".into())),
+            };
+            params.push((name, ty));
+        }
+        Ok((ret, params))
+    }
+
+    fn add_function(&mut self, docs: Vec<String>, sig: &Signature) {
+        match self.map_signature(sig) {
+            Ok((ret, params)) => self.functions.push(Function {
+                docs,
+                ret,
+                name: sig.ident.unraw().to_string(),
+                params,
+            }),
+            Err((what, e)) => self
+                .skipped
+                .push(format!("fn {}: {}: {}", sig.ident, what, e)),
+        }
+    }
+
+    /// Adds the shim generated by `#[export]`, following the same rules as `cursed-macros`.
+    fn add_export(&mut self, docs: Vec<String>, sig: &Signature) {
+        let mut shim = sig.clone();
+        for input in shim.inputs.iter_mut() {
+            if let FnArg::Typed(pat) = input {
+                let ty = &*pat.ty;
+                let mapped: syn::Type = match ty {
+                    syn::Type::Reference(r) => match (&*r.elem, ty::segment(&r.elem)) {
+                        (syn::Type::Slice(slice), _) => {
+                            let elem = &slice.elem;
+                            match r.mutability {
+                                Some(_) => syn::parse_quote!(OutSlice<#elem>),
+                                None => syn::parse_quote!(InSlice<#elem>),
+                            }
+                        }
+                        (_, Some((ref name, None))) if name == "str" => {
+                            syn::parse_quote!(In<c_char>)
+                        }
+                        _ => {
+                            let elem = &r.elem;
+                            match r.mutability {
+                                Some(_) => syn::parse_quote!(InOut<#elem>),
+                                None => syn::parse_quote!(In<#elem>),
+                            }
+                        }
+                    },
+                    ty => match ty::segment(ty) {
+                        Some((ref name, Some(inner))) if name == "ArcPtr" || name == "Arc" => {
+                            syn::parse_quote!(In<ArcPtr<#inner>>)
+                        }
+                        Some((ref name, Some(inner))) if name == "Vec" => {
+                            syn::parse_quote!(In<Vec<#inner>>)
+                        }
+                        _ => ty.clone(),
+                    },
+                };
+                *pat.ty = mapped;
+            }
+        }
+
+        if let ReturnType::Type(_, ret) = &mut shim.output {
+            let ok = match ty::segment(ret) {
+                Some((ref name, Some(ok))) if name == "Result" => ok.clone(),
+                _ => (**ret).clone(),
+            };
+            let is_value = match &ok {
+                syn::Type::Tuple(t) => t.elems.is_empty(),
+                ty => is_primitive_name(ty),
+            };
+            **ret = match ty::segment(&ok) {
+                _ if is_value => ok,
+                Some((ref name, Some(_))) if name == "ArcPtr" => ok,
+                Some((ref name, Some(inner))) if name == "Arc" => syn::parse_quote!(ArcPtr<#inner>),
+                Some((ref name, Some(_))) if name == "Vec" => syn::parse_quote!(Nullable<#ok>),
+                _ => syn::parse_quote!(ArcPtr<#ok>),
+            };
+        }
+
+        shim.inputs
+            .push(syn::parse_quote!(exception: OutPtr<Exception>));
+        self.add_function(docs, &shim);
+    }
+
+    /// Adds the statics and functions emitted by one of the `GENERATORS`.
+    fn add_generated(&mut self, generator: &Generator, tokens: &proc_macro2::TokenStream) {
+        use syn::parse::Parser;
+        use syn::punctuated::Punctuated;
+
+        let entries =
+            Punctuated::<GeneratedType, syn::Token![,]>::parse_terminated.parse2(tokens.clone());
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.skipped.push(format!("{}!: {}", generator.name, e));
+                return;
+            }
+        };
+        if let Some(entry) = entries
+            .iter()
+            .find(|x| x.types.len() != generator.types.len())
+        {
+            self.skipped.push(format!(
+                "{}!: {}: expected one type for each of {}",
+                generator.name,
+                entry.name,
+                generator.types.join(", ")
+            ));
+            return;
+        }
+
+        if !self.structs.iter().any(|x| x.name == "TypeTag") {
+            self.structs.push(Struct {
+                docs: vec!["A stable identifier for a type.".into()],
+                name: "TypeTag".into(),
+                fields: vec![("id".into(), CType::Named("uint64_t".into()))],
+            });
+        }
+
+        for entry in entries {
+            let name = entry.name.to_string();
+            self.statics.push(Static {
+                docs: vec![format!("Type tag for `{}`.", entry.types.join(" => "))],
+                id: Some(type_tag(&name)),
+                name,
+                ty: CType::Named("TypeTag".into()),
+            });
+        }
+
+        for (docs, sig) in generator.functions.iter() {
+            let sig: Signature = syn::parse_str(sig).expect("generated signatures are valid");
+            let docs = match docs.is_empty() {
+                true => vec![],
+                false => vec![docs.to_string()],
+            };
+            self.add_function(docs, &sig);
+        }
+    }
+
+    /// Skips every function and struct using an `Option<F>` where `F` is not a function pointer
+    /// type alias, which can only be checked once every module has been parsed.
+    fn resolve_optionals(&mut self) {
+        let typedefs: BTreeSet<String> = self.typedefs.iter().map(|x| x.name.clone()).collect();
+        let invalid = |ty: &CType| match ty {
+            CType::Optional(name) if !typedefs.contains(name) => Some(name.clone()),
+            _ => None,
+        };
+
+        let mut skipped = vec![];
+        self.functions.retain(|f| {
+            let types = std::iter::once(&f.ret).chain(f.params.iter().map(|(_, ty)| ty));
+            match types.flat_map(CType::parts).find_map(invalid) {
+                Some(name) => {
+                    skipped.push(format!(
+                        "fn {}: `{}` is not a function pointer",
+                        f.name, name
+                    ));
+                    false
+                }
+                None => true,
+            }
+        });
+        self.structs.retain(|s| {
+            match s
+                .fields
+                .iter()
+                .flat_map(|(_, ty)| ty.parts())
+                .find_map(invalid)
+            {
+                Some(name) => {
+                    skipped.push(format!(
+                        "struct {}: `{}` is not a function pointer",
+                        s.name, name
+                    ));
+                    false
+                }
+                None => true,
+            }
+        });
+        self.skipped.extend(skipped);
+    }
+
+    fn add_struct(&mut self, item: &syn::ItemStruct) {
+        if !item.generics.params.is_empty() {
+            return;
+        }
+        let fields = match &item.fields {
+            Fields::Named(fields) => fields,
+            _ => return,
+        };
+
+        let mut out = vec![];
+        for field in fields.named.iter() {
+            let name = field.ident.as_ref().unwrap().to_string();
+            match ty::map(&field.ty) {
+                Ok(ty) => out.push((name, ty)),
+                Err(e) => {
+                    self.skipped
+                        .push(format!("struct {}: {}: {}", item.ident, name, e));
+                    return;
+                }
+            }
+        }
+        self.structs.push(Struct {
+            docs: docs(&item.attrs),
+            name: item.ident.to_string(),
+            fields: out,
+        });
+    }
+
+    fn add_enum(&mut self, item: &syn::ItemEnum) {
+        let repr = reprs(&item.attrs)
+            .into_iter()
+            .filter_map(|x| ty::map(&syn::parse_str(&x).ok()?).ok())
+            .find(|x| !x.is_ptr() && x != &CType::Named("C".into()));
+        let repr = match repr {
+            Some(CType::Named(repr)) => repr,
+            _ => return,
+        };
+
+        let prefix = screaming_snake(&item.ident.to_string());
+        let mut next = 0i64;
+        let mut variants = vec![];
+        for variant in item.variants.iter() {
+            if let Some((
+                _,
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: Lit::Int(i), ..
+                }),
+            )) = &variant.discriminant
+            {
+                next = i.base10_parse().unwrap_or(next);
+            }
+            variants.push((
+                format!("{}_{}", prefix, screaming_snake(&variant.ident.to_string())),
+                next.to_string(),
+            ));
+            next += 1;
+        }
+        self.enums.push(Enum {
+            docs: docs(&item.attrs),
+            name: item.ident.to_string(),
+            repr,
+            variants,
+        });
+    }
+
+    /// Every type used in a function, field, static or typedef.
+    fn all_types(&self) -> impl Iterator<Item = &CType> {
+        let fn_types = self
+            .functions
+            .iter()
+            .flat_map(|f| std::iter::once(&f.ret).chain(f.params.iter().map(|(_, ty)| ty)));
+        let field_types = self
+            .structs
+            .iter()
+            .flat_map(|s| s.fields.iter().map(|(_, ty)| ty));
+        let static_types = self.statics.iter().map(|s| &s.ty);
+        let typedef_types = self.typedefs.iter().map(|t| &t.ty);
+
+        fn_types
+            .chain(field_types)
+            .chain(static_types)
+            .chain(typedef_types)
+    }
+
+    fn opaque_types(&self) -> BTreeSet<&str> {
+        let defined: BTreeSet<&str> = self
+            .structs
+            .iter()
+            .map(|x| &*x.name)
+            .chain(self.enums.iter().map(|x| &*x.name))
+            .chain(self.typedefs.iter().map(|x| &*x.name))
+            .collect();
+
+        self.all_types()
+            .flat_map(CType::parts)
+            .filter_map(CType::base_name)
+            .filter(|x| x.chars().next().map(char::is_uppercase).unwrap_or(false))
+            .filter(|x| !defined.contains(x))
+            .collect()
+    }
+
+    /// Every distinct instance of a generic type used, such as `Callback<A, R>` or `InSlice<T>`,
+    /// each of which is declared as its own struct.
+    fn generic_structs(&self) -> Vec<&CType> {
+        let mut out: Vec<&CType> = vec![];
+        for ty in self.all_types().flat_map(CType::parts) {
+            if ty.fields().is_some() && !out.contains(&ty) {
+                out.push(ty);
+            }
+        }
+        out
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_string())
+    }
+}
+
+fn is_primitive_name(ty: &syn::Type) -> bool {
+    match ty::map(ty) {
+        Ok(CType::Named(name)) => !name.chars().next().map(char::is_uppercase).unwrap_or(true),
+        _ => false,
+    }
+}
+
+/// An entry of a `GENERATORS` macro invocation, `TAG_NAME => Type => ...`.
+struct GeneratedType {
+    name: syn::Ident,
+    types: Vec<String>,
+}
+
+impl syn::parse::Parse for GeneratedType {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        let mut types = vec![];
+        while input.peek(syn::Token![=>]) {
+            input.parse::<syn::Token![=>]>()?;
+            let ty: syn::Type = input.parse()?;
+            types.push(quote::quote!(#ty).to_string().replace(' ', ""));
+        }
+        Ok(GeneratedType { name, types })
+    }
+}
+
+fn write_docs(out: &mut String, docs: &[String], indent: &str) {
+    if docs.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "{}/**", indent);
+    for line in docs {
+        if line.is_empty() {
+            let _ = writeln!(out, "{} *", indent);
+        } else {
+            let _ = writeln!(out, "{} * {}", indent, line);
+        }
+    }
+    let _ = writeln!(out, "{} */", indent);
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        let guard = &self.include_guard;
+
+        let _ = writeln!(out, "/* Generated by cursed-header. Do not edit. */\n");
+        let _ = writeln!(out, "#ifndef {}\n#define {}\n", guard, guard);
+        let _ = writeln!(
+            out,
+            "#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n"
+        );
+        let _ = writeln!(out, "#ifdef __cplusplus\nextern \"C\" {{\n#endif\n");
+
+        for name in self.opaque_types() {
+            let _ = writeln!(out, "typedef struct {} {};", name, name);
+        }
+        let _ = writeln!(out);
+
+        for e in self.enums.iter() {
+            write_docs(&mut out, &e.docs, "");
+            // An anonymous enum, as the typedef fixes the size to that of the `repr`.
+            let _ = writeln!(out, "enum {{");
+            for (name, value) in e.variants.iter() {
+                let _ = writeln!(out, "    {} = {},", name, value);
+            }
+            let _ = writeln!(out, "}};\ntypedef {} {};\n", e.repr, e.name);
+        }
+
+        for t in self.typedefs.iter() {
+            write_docs(&mut out, &t.docs, "");
+            let _ = writeln!(out, "typedef {};\n", t.ty.declare(&t.name));
+        }
+
+        for ty in self.generic_structs() {
+            let _ = writeln!(out, "typedef struct {} {{", ty);
+            for (name, field) in ty.fields().unwrap_or_default() {
+                let _ = writeln!(out, "    {};", field.declare(name));
+            }
+            let _ = writeln!(out, "}} {};\n", ty);
+        }
+
+        for s in self.structs.iter() {
+            write_docs(&mut out, &s.docs, "");
+            let _ = writeln!(out, "typedef struct {} {{", s.name);
+            for (name, ty) in s.fields.iter() {
+                let _ = writeln!(out, "    {};", ty.declare(name));
+            }
+            let _ = writeln!(out, "}} {};\n", s.name);
+        }
+
+        for s in self.statics.iter() {
+            write_docs(&mut out, &s.docs, "");
+            if let Some(id) = s.id {
+                let _ = writeln!(out, "#define {}_ID UINT64_C(0x{:016x})", s.name, id);
+            }
+            let _ = writeln!(out, "extern const {};\n", s.ty.declare(&s.name));
+        }
+
+        for func in self.functions.iter() {
+            write_docs(&mut out, &func.docs, "");
+            let params = if func.params.is_empty() {
+                "void".to_string()
+            } else {
+                func.params
+                    .iter()
+                    .map(|(name, ty)| ty.declare(name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let _ = writeln!(out, "{} {}({});\n", func.ret, func.name, params);
+        }
+
+        for skipped in self.skipped.iter() {
+            let _ = writeln!(out, "/* skipped {} */", skipped);
+        }
+        if !self.skipped.is_empty() {
+            let _ = writeln!(out);
+        }
+
+        let _ = writeln!(out, "#ifdef __cplusplus\n}}\n#endif\n");
+        let _ = writeln!(out, "#endif /* {} */", guard);
+
+        f.write_str(&out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursed_header() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../src/lib.rs");
+        let header = Builder::new(root).generate().unwrap().to_string();
+
+        assert!(header.contains("typedef struct RawVec RawVec;"));
+        assert!(header.contains("size_t vec_len(const RawVec* handle, Exception** exception);"));
+        assert!(header.contains("const void* arc_clone(const void* arc);"));
+        assert!(header.contains("const void* weak_upgrade(const void* weak);"));
+        assert!(header.contains("EXCEPTION_KIND_NULL_ARGUMENT = 1,"));
+        assert!(header.contains("typedef uint32_t ExceptionKind;"));
+        assert!(header.contains(" * Frees an exception and its causes."));
+        assert!(header.contains("extern const TypeTag PRIMITIVE_F64;"));
+        assert!(header.contains("void cursed_string_free(char* ptr);"));
+        assert!(header
+            .contains("typedef struct CCharBuf {\n    char* ptr;\n    size_t len;\n} CCharBuf;"));
+        assert!(
+            header.contains("typedef void (*CompletionCallback)(void*, const void*, Exception*);")
+        );
+        assert!(header.contains(
+            "const void* vec_as_ptr(const RawPrimitiveVec* handle, Exception** exception);"
+        ));
+        assert!(!header.contains("counter_add"), "test modules are skipped");
+    }
+
+    #[test]
+    fn type_tags() {
+        assert_eq!(type_tag(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(type_tag("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn exports_and_vec_ffi() {
+        let mut header = Header::default();
+        let file: syn::File = syn::parse_quote! {
+            #[export]
+            fn counter_add(counter: &mut Counter, name: &str, thing: ArcPtr<Thing>) -> Result<u64, Error> {
+                unimplemented!()
+            }
+
+            #[export]
+            fn counter_copy(counter: Arc<Counter>) -> Arc<Counter> {
+                unimplemented!()
+            }
+
+            generate_vec_ffi! {
+                TYPE_U64 => u64
+            }
+
+            cursed::generate_map_ffi! {
+                MAP_U64_STRING => u64 => String
+            }
+
+            generate_map_ffi! {
+                MAP_U64 => u64
+            }
+        };
+        header.parse_items(&file.items, Path::new(".")).unwrap();
+        let header = header.to_string();
+
+        assert!(header.contains(
+            "uint64_t counter_add(Counter* counter, const char* name, const Thing* thing, Exception** exception);"
+        ));
+        assert!(header.contains(
+            "const Counter* counter_copy(const Counter* counter, Exception** exception);"
+        ));
+        assert!(header.contains("#define TYPE_U64_ID UINT64_C(0x"));
+        assert!(header.contains("extern const TypeTag TYPE_U64;"));
+        assert!(header.contains("typedef struct TypeTag {\n    uint64_t id;\n} TypeTag;"));
+        assert!(header.contains("RawVec* vec_new(TypeTag ty);"));
+        assert!(
+            header.contains("void vec_free(RawVec* handle, TypeTag ty, Exception** exception);")
+        );
+        assert!(header.contains(" * Type tag for `u64 => String`."));
+        assert!(header.contains("extern const TypeTag MAP_U64_STRING;"));
+        assert!(header.contains(
+            "bool map_insert(const RawMap* handle, TypeTag ty, const void* key, const void* value, Exception** exception);"
+        ));
+        assert!(header.contains(
+            "RawMapIter* map_iter_new(const RawMap* handle, TypeTag ty, Exception** exception);"
+        ));
+        assert!(header.contains("typedef struct RawMapIter RawMapIter;"));
+        assert!(header.contains(
+            "/* skipped generate_map_ffi!: MAP_U64: expected one type for each of key, value */"
+        ));
+    }
+
+    /// Every `#[no_mangle]` function in cursed, including those emitted by the `GENERATORS`.
+    fn exported_functions(dir: &Path, out: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                exported_functions(&path, out);
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let source = source.split("#[cfg(test)]").next().unwrap();
+            let mut lines = source.lines().map(str::trim);
+            while let Some(line) = lines.next() {
+                if line != "#[no_mangle]" {
+                    continue;
+                }
+                let item = lines.next().unwrap_or_default();
+                if let Some(rest) = item.strip_prefix("pub extern \"C\" fn ") {
+                    out.push(rest.split('(').next().unwrap().to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covers_every_export() {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("../src");
+        let mut header = Builder::new(src.join("lib.rs")).generate().unwrap();
+        for generator in GENERATORS {
+            let types = vec!["u8"; generator.types.len()].join(" => ");
+            let tokens = format!("TAG => {}", types).parse().unwrap();
+            header.add_generated(generator, &tokens);
+        }
+        let header = header.to_string();
+
+        let mut functions = vec![];
+        exported_functions(&src, &mut functions);
+        assert!(functions.len() > 90);
+        for name in functions {
+            assert!(
+                header.contains(&format!(" {}(", name)),
+                "{} is missing",
+                name
+            );
+        }
+        assert!(!header.contains("/* skipped"), "{}", header);
+        // `Nullable<T>` is always a boxed `T`, even when `T` is a pointer.
+        assert!(header.contains("const RawValue** vec_pop(RawVec* handle, Exception** exception);"));
+    }
+
+    #[test]
+    fn optional_function_pointers() {
+        let mut header = Header::default();
+        let file: syn::File = syn::parse_quote! {
+            #[no_mangle]
+            pub extern "C" fn adopt(release: Option<Release>, on_done: Option<extern "C" fn()>) {}
+
+            #[no_mangle]
+            pub extern "C" fn invalid(thing: Option<Thing>) {}
+
+            pub type Release = extern "C" fn(user_data: *mut c_void);
+        };
+        header.parse_items(&file.items, Path::new(".")).unwrap();
+        header.resolve_optionals();
+        let header = header.to_string();
+
+        assert!(header.contains("void adopt(Release release, void (*on_done)(void));"));
+        assert!(header.contains("/* skipped fn invalid: `Thing` is not a function pointer */"));
+        assert!(!header.contains("typedef struct Thing Thing;"));
+    }
+
+    #[test]
+    fn callbacks_and_typedefs() {
+        let mut header = Header::default();
+        let file: syn::File = syn::parse_quote! {
+            pub type OnDone = extern "C" fn(user_data: *mut c_void, ok: bool);
+
+            #[no_mangle]
+            pub extern "C" fn watch(on_change: In<Callback<In<Thing>, ()>>, on_done: OnDone) {}
+
+            #[export]
+            fn fill(buf: &mut [u8], from: &[u8]) {}
+        };
+        header.parse_items(&file.items, Path::new(".")).unwrap();
+        let header = header.to_string();
+
+        assert!(header.contains("typedef struct Thing Thing;"));
+        assert!(header.contains(
+            "typedef struct InSlice_uint8_t {\n    const uint8_t* ptr;\n    size_t len;\n} InSlice_uint8_t;"
+        ));
+        assert!(header.contains(
+            "void fill(OutSlice_uint8_t buf, InSlice_uint8_t from, Exception** exception);"
+        ));
+        assert!(header.contains("typedef void (*OnDone)(void*, bool);"));
+        assert!(header.contains(
+            "typedef struct Callback_const_Thing_ptr_void {\n    \
+             void (*callback)(void*, const Thing*);\n    \
+             void* user_data;\n    \
+             void (*release)(void*);\n\
+             } Callback_const_Thing_ptr_void;"
+        ));
+        assert!(header.contains(
+            "void watch(const Callback_const_Thing_ptr_void* on_change, OnDone on_done);"
+        ));
+    }
+}
@@ -0,0 +1,394 @@
+use syn::{GenericArgument, PathArguments, Type};
+
+/// A C type, as far as the generated header needs to understand one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CType {
+    Void,
+    /// A primitive, or a struct, enum or opaque type by name.
+    Named(String),
+    Ptr {
+        pointee: Box<CType>,
+        is_const: bool,
+    },
+    /// A function pointer, from an `extern "C" fn` type.
+    Fn {
+        ret: Box<CType>,
+        params: Vec<CType>,
+    },
+    /// An `Option<F>` of a type alias `F`, which is only FFI-safe if `F` is a function pointer.
+    Optional(String),
+    /// A `cursed::callback::Callback<A, R>`, declared as a struct named after its arguments.
+    Callback {
+        arg: Box<CType>,
+        ret: Box<CType>,
+    },
+    /// A `cursed::inout::InSlice<T>` or `OutSlice<T>`, declared as a struct named after `T`.
+    Slice {
+        elem: Box<CType>,
+        is_const: bool,
+    },
+}
+
+/// Turns a C type into something usable in an identifier, e.g. `const Foo*` to `const_Foo_ptr`.
+fn mangle(ty: &CType) -> String {
+    ty.to_string()
+        .replace('*', " ptr")
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+impl CType {
+    pub(crate) fn ptr(pointee: CType, is_const: bool) -> CType {
+        CType::Ptr {
+            pointee: Box::new(pointee),
+            is_const,
+        }
+    }
+
+    pub fn is_ptr(&self) -> bool {
+        matches!(self, CType::Ptr { .. })
+    }
+
+    /// The name of the non-pointer type at the bottom of this type, if it is not `void`.
+    pub fn base_name(&self) -> Option<&str> {
+        match self {
+            CType::Void => None,
+            CType::Named(name) | CType::Optional(name) => Some(name),
+            CType::Ptr { pointee, .. } => pointee.base_name(),
+            CType::Fn { .. } | CType::Callback { .. } | CType::Slice { .. } => None,
+        }
+    }
+
+    /// Every type this type is built from, including itself.
+    pub fn parts(&self) -> Vec<&CType> {
+        let mut out = vec![self];
+        match self {
+            CType::Ptr { pointee, .. } => out.extend(pointee.parts()),
+            CType::Fn { ret, params } => {
+                out.extend(ret.parts());
+                out.extend(params.iter().flat_map(CType::parts));
+            }
+            CType::Callback { arg, ret } => {
+                out.extend(arg.parts());
+                out.extend(ret.parts());
+            }
+            CType::Slice { elem, .. } => out.extend(elem.parts()),
+            _ => {}
+        }
+        out
+    }
+
+    /// The fields of the struct that must be declared for a generic type, e.g. `Callback<A, R>`.
+    pub fn fields(&self) -> Option<Vec<(&'static str, CType)>> {
+        let user_data = CType::ptr(CType::Void, false);
+        match self {
+            CType::Callback { arg, ret } => Some(vec![
+                (
+                    "callback",
+                    CType::Fn {
+                        ret: ret.clone(),
+                        params: vec![user_data.clone(), (**arg).clone()],
+                    },
+                ),
+                ("user_data", user_data.clone()),
+                (
+                    "release",
+                    CType::Fn {
+                        ret: Box::new(CType::Void),
+                        params: vec![user_data],
+                    },
+                ),
+            ]),
+            CType::Slice { elem, is_const } => Some(vec![
+                ("ptr", CType::ptr((**elem).clone(), *is_const)),
+                ("len", CType::Named("size_t".into())),
+            ]),
+            _ => None,
+        }
+    }
+
+    /// Renders a declaration of `name` with this type, e.g. `const RawVec* handle`.
+    pub fn declare(&self, name: &str) -> String {
+        if let CType::Fn { ret, params } = self {
+            let params = match params.is_empty() {
+                true => "void".to_string(),
+                false => params
+                    .iter()
+                    .map(CType::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            return format!("{} (*{})({})", ret, name, params);
+        }
+
+        let ty = self.to_string();
+        if name.is_empty() {
+            ty
+        } else {
+            format!("{} {}", ty, name)
+        }
+    }
+}
+
+impl std::fmt::Display for CType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CType::Void => f.write_str("void"),
+            CType::Named(name) | CType::Optional(name) => f.write_str(name),
+            CType::Ptr { pointee, is_const } => match (&**pointee, is_const) {
+                (CType::Ptr { .. }, true) => write!(f, "{} const*", pointee),
+                (_, true) => write!(f, "const {}*", pointee),
+                (_, false) => write!(f, "{}*", pointee),
+            },
+            CType::Fn { .. } => f.write_str(&self.declare("")),
+            CType::Callback { arg, ret } => {
+                write!(f, "Callback_{}_{}", mangle(arg), mangle(ret))
+            }
+            CType::Slice { elem, is_const } => match is_const {
+                true => write!(f, "InSlice_{}", mangle(elem)),
+                false => write!(f, "OutSlice_{}", mangle(elem)),
+            },
+        }
+    }
+}
+
+fn primitive(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "bool" => "bool",
+        "i8" => "int8_t",
+        "i16" => "int16_t",
+        "i32" => "int32_t",
+        "i64" => "int64_t",
+        "isize" => "intptr_t",
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "usize" => "size_t",
+        "f32" => "float",
+        "f64" => "double",
+        "c_char" => "char",
+        "c_int" => "int",
+        "c_uint" => "unsigned int",
+        _ => return None,
+    })
+}
+
+/// Returns the last path segment's identifier and its first generic type argument, if any.
+pub fn segment(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    let arg = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|x| match x {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+    Some((segment.ident.to_string(), arg))
+}
+
+/// Returns every generic type argument of the last path segment.
+fn segment_args(ty: &Type) -> Option<Vec<&Type>> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => Some(
+            args.args
+                .iter()
+                .filter_map(|x| match x {
+                    GenericArgument::Type(ty) => Some(ty),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn arg<'a>(name: &str, arg: Option<&'a Type>) -> Result<&'a Type, String> {
+    arg.ok_or_else(|| format!("`{}` requires a type argument", name))
+}
+
+/// Maps a Rust type used in an FFI signature to its C equivalent.
+///
+/// The cursed pointer newtypes are mapped to typed pointers: `In<T>` to `const T*`, `Out<T>` and
+/// `InOut<T>` to `T*`, and `OutPtr<T>` to `T**`. `ArcPtr<T>` and `WeakPtr<T>` are the `Arc`'s
+/// data pointer, and `Nullable<T>` is a nullable `T*` to a boxed `T`, even when `T` is itself a
+/// pointer. `Option<extern "C" fn>` is a nullable function pointer.
+pub fn map(ty: &Type) -> Result<CType, String> {
+    match ty {
+        Type::Tuple(tuple) if tuple.elems.is_empty() => Ok(CType::Void),
+        Type::Paren(paren) => map(&paren.elem),
+        Type::Group(group) => map(&group.elem),
+        Type::Ptr(ptr) => Ok(CType::ptr(map(&ptr.elem)?, ptr.mutability.is_none())),
+        Type::Reference(r) => Ok(CType::ptr(map(&r.elem)?, r.mutability.is_none())),
+        Type::BareFn(f) => {
+            let is_c = f
+                .abi
+                .as_ref()
+                .and_then(|x| x.name.as_ref())
+                .map(|x| x.value() == "C")
+                .unwrap_or(false);
+            if !is_c {
+                return Err("function pointers must be `extern \"C\"`".into());
+            }
+            let ret = match &f.output {
+                syn::ReturnType::Default => CType::Void,
+                syn::ReturnType::Type(_, ty) => map(ty)?,
+            };
+            let params = f
+                .inputs
+                .iter()
+                .map(|x| map(&x.ty))
+                .collect::<Result<_, _>>()?;
+            Ok(CType::Fn {
+                ret: Box::new(ret),
+                params,
+            })
+        }
+        Type::Path(_) => {
+            let (name, inner) = segment(ty).ok_or_else(|| "unsupported path".to_string())?;
+            match &*name {
+                "In" => {
+                    let inner = map(arg(&name, inner)?)?;
+                    match inner {
+                        // `In<ArcPtr<T>>` is the `Arc` pointer itself, see `In::as_arc`.
+                        CType::Ptr { .. } => Ok(inner),
+                        inner => Ok(CType::ptr(inner, true)),
+                    }
+                }
+                "Out" | "InOut" => Ok(CType::ptr(map(arg(&name, inner)?)?, false)),
+                "OutPtr" => Ok(CType::ptr(
+                    CType::ptr(map(arg(&name, inner)?)?, false),
+                    false,
+                )),
+                "InRaw" => Ok(CType::ptr(CType::Void, true)),
+                "OutRaw" | "InOutRaw" => Ok(CType::ptr(CType::Void, false)),
+                "ArcPtr" | "Arc" | "WeakPtr" => Ok(CType::ptr(map(arg(&name, inner)?)?, true)),
+                "Nullable" => Ok(CType::ptr(map(arg(&name, inner)?)?, false)),
+                "Callback" => {
+                    let args = match segment_args(ty) {
+                        Some(args) if args.len() == 2 => args,
+                        _ => return Err("`Callback` requires two type arguments".into()),
+                    };
+                    Ok(CType::Callback {
+                        arg: Box::new(map(args[0])?),
+                        ret: Box::new(map(args[1])?),
+                    })
+                }
+                // `cursed::handles::Handle<T>` is a transparent wrapper over its ID.
+                "Handle" => Ok(CType::Named("uint64_t".into())),
+                // `cursed::c_char::CCharPtr` is a transparent wrapper over an owned `char*`.
+                "CCharPtr" => Ok(CType::ptr(CType::Named("char".into()), false)),
+                "InSlice" | "OutSlice" => Ok(CType::Slice {
+                    elem: Box::new(map(arg(&name, inner)?)?),
+                    is_const: name == "InSlice",
+                }),
+                // `cursed::vec::Vec<T>` is a transparent wrapper over `RawVec`.
+                "Vec" => Ok(CType::Named("RawVec".into())),
+                "Option" => match map(arg(&name, inner)?)? {
+                    f @ CType::Fn { .. } => Ok(f),
+                    CType::Named(alias) if alias.starts_with(char::is_uppercase) => {
+                        Ok(CType::Optional(alias))
+                    }
+                    _ => Err("only function pointers can be optional".into()),
+                },
+                "c_void" => Ok(CType::Void),
+                _ => match (primitive(&name), inner) {
+                    (Some(c), _) => Ok(CType::Named(c.into())),
+                    (None, None) => Ok(CType::Named(name)),
+                    (None, Some(_)) => Err(format!("unsupported generic type `{}`", name)),
+                },
+            }
+        }
+        _ => Err("unsupported type".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn c(ty: Type) -> String {
+        map(&ty).unwrap().to_string()
+    }
+
+    #[test]
+    fn pointers() {
+        assert_eq!(c(parse_quote!(In<RawVec>)), "const RawVec*");
+        assert_eq!(c(parse_quote!(InOut<RawVec>)), "RawVec*");
+        assert_eq!(c(parse_quote!(OutPtr<Exception>)), "Exception**");
+        assert_eq!(c(parse_quote!(InRaw)), "const void*");
+        assert_eq!(c(parse_quote!(*mut libc::c_char)), "char*");
+        assert_eq!(c(parse_quote!(In<ArcPtr<Foo>>)), "const Foo*");
+        assert_eq!(c(parse_quote!(Nullable<ArcPtr<c_void>>)), "const void**");
+        assert_eq!(c(parse_quote!(Nullable<Arc<RawValue>>)), "const RawValue**");
+        assert_eq!(c(parse_quote!(Handle<Foo>)), "uint64_t");
+        assert_eq!(c(parse_quote!(Nullable<Exception>)), "Exception*");
+        assert_eq!(c(parse_quote!(CCharPtr)), "char*");
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(c(parse_quote!(usize)), "size_t");
+        assert_eq!(c(parse_quote!(())), "void");
+        assert_eq!(c(parse_quote!(bool)), "bool");
+    }
+
+    #[test]
+    fn function_pointers() {
+        let ty = map(&parse_quote!(extern "C" fn(*mut c_void, u32) -> bool)).unwrap();
+        assert_eq!(ty.declare("callback"), "bool (*callback)(void*, uint32_t)");
+        assert_eq!(c(parse_quote!(extern "C" fn())), "void (*)(void)");
+        assert!(map(&parse_quote!(fn(u32))).is_err());
+    }
+
+    #[test]
+    fn callbacks() {
+        assert_eq!(
+            c(parse_quote!(Callback<u64, bool>)),
+            "Callback_uint64_t_bool"
+        );
+        assert_eq!(
+            c(parse_quote!(In<Callback<In<Foo>, ()>>)),
+            "const Callback_const_Foo_ptr_void*"
+        );
+        assert!(map(&parse_quote!(Callback<u64>)).is_err());
+    }
+
+    #[test]
+    fn slices() {
+        let ty = map(&parse_quote!(InSlice<u32>)).unwrap();
+        assert_eq!(ty.to_string(), "InSlice_uint32_t");
+        assert_eq!(ty.fields().unwrap()[0].1.to_string(), "const uint32_t*");
+        assert_eq!(c(parse_quote!(OutSlice<Foo>)), "OutSlice_Foo");
+    }
+
+    #[test]
+    fn optional_functions() {
+        assert_eq!(
+            c(parse_quote!(Option<extern "C" fn(*mut c_void)>)),
+            "void (*)(void*)"
+        );
+        assert_eq!(
+            map(&parse_quote!(Option<OnDone>)).unwrap(),
+            CType::Optional("OnDone".into())
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(map(&parse_quote!(Option<u32>)).is_err());
+        assert!(map(&parse_quote!(Option<*mut Foo>)).is_err());
+        assert!(map(&parse_quote!([u8; 4])).is_err());
+    }
+}
@@ -0,0 +1,454 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, FnArg, GenericArgument, ItemFn, Pat, PathArguments,
+    ReturnType, Type,
+};
+
+const PRIMITIVES: &[&str] = &[
+    "bool", "i8", "i16", "i32", "i64", "isize", "u8", "u16", "u32", "u64", "usize", "f32", "f64",
+];
+
+/// The paths `cursed::vec::Vec` may be written as. A bare `Vec` is taken to be the one from
+/// `cursed::prelude`.
+const VEC_PATHS: &[&str] = &["Vec", "vec::Vec", "cursed::vec::Vec"];
+/// The standard library's `Vec`, which cannot be shared with C.
+const STD_VEC_PATHS: &[&str] = &["std::vec::Vec", "alloc::vec::Vec"];
+const ARC_PTR_PATHS: &[&str] = &["ArcPtr", "sync::ArcPtr", "cursed::sync::ArcPtr"];
+const ARC_PATHS: &[&str] = &["Arc", "std::sync::Arc", "alloc::sync::Arc"];
+const RESULT_PATHS: &[&str] = &["Result", "core::result::Result", "std::result::Result"];
+
+/// How an argument of the annotated function crosses the FFI.
+enum Arg {
+    /// `&T`, passed as `In<T>`.
+    Ref(Type),
+    /// `&mut T`, passed as `InOut<T>`.
+    RefMut(Type),
+    /// `&str`, passed as a NUL-terminated `In<c_char>`.
+    Str,
+    /// `&[T]`, passed as an `InSlice<T>`.
+    Slice(Type),
+    /// `&mut [T]`, passed as an `OutSlice<T>`.
+    SliceMut(Type),
+    /// `ArcPtr<T>`, passed as a borrowed `In<ArcPtr<T>>` and cloned.
+    ArcPtr(Type),
+    /// `Arc<T>`, passed as a borrowed `In<ArcPtr<T>>` and copied into a new `Arc`.
+    Arc(Type),
+    /// `cursed::vec::Vec<T>`, passed as a borrowed `In<Vec<T>>` and cloned.
+    Vec(Type),
+    /// Anything else is assumed to be FFI-safe and passed through.
+    Value(Type),
+}
+
+/// How the (successful) return value of the annotated function crosses the FFI.
+enum Ret {
+    Unit,
+    /// Primitives are returned by value, with `Default::default()` on failure.
+    Primitive(Type),
+    /// `ArcPtr<T>`, returned as is.
+    ArcPtr(Type),
+    /// `Arc<T>`, moved or copied into a new `ArcPtr<T>`.
+    Arc(Type),
+    /// `cursed::vec::Vec<T>`, returned as an owned `Nullable<Vec<T>>`.
+    Vec(Type),
+    /// Anything else is moved into a new `ArcPtr<T>`.
+    Other(Type),
+}
+
+/// Returns the type's path as written, such as `cursed::vec::Vec` without any leading `::`, and
+/// the single generic type argument of its last segment, if any.
+fn generic_arg(ty: &Type) -> Option<(String, Option<&Type>)> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    let arg = match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|x| match x {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    };
+    let name = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::");
+    Some((name, arg))
+}
+
+fn is_one_of(name: &str, paths: &[&str]) -> bool {
+    paths.contains(&name)
+}
+
+fn is_primitive(ty: &Type) -> bool {
+    match generic_arg(ty) {
+        Some((name, None)) => PRIMITIVES.contains(&&*name),
+        _ => false,
+    }
+}
+
+fn classify_arg(ty: &Type) -> Arg {
+    if let Type::Reference(r) = ty {
+        if let Type::Slice(slice) = &*r.elem {
+            return match r.mutability {
+                Some(_) => Arg::SliceMut((*slice.elem).clone()),
+                None => Arg::Slice((*slice.elem).clone()),
+            };
+        }
+        if let Some((name, None)) = generic_arg(&r.elem) {
+            if name == "str" && r.mutability.is_none() {
+                return Arg::Str;
+            }
+        }
+        return match r.mutability {
+            Some(_) => Arg::RefMut((*r.elem).clone()),
+            None => Arg::Ref((*r.elem).clone()),
+        };
+    }
+
+    match generic_arg(ty) {
+        Some((ref name, Some(inner))) if is_one_of(name, ARC_PTR_PATHS) => {
+            Arg::ArcPtr(inner.clone())
+        }
+        Some((ref name, Some(inner))) if is_one_of(name, ARC_PATHS) => Arg::Arc(inner.clone()),
+        Some((ref name, Some(inner))) if is_one_of(name, VEC_PATHS) => Arg::Vec(inner.clone()),
+        _ => Arg::Value(ty.clone()),
+    }
+}
+
+fn classify_ret(ty: &Type) -> Ret {
+    if let Type::Tuple(tuple) = ty {
+        if tuple.elems.is_empty() {
+            return Ret::Unit;
+        }
+    }
+
+    if is_primitive(ty) {
+        return Ret::Primitive(ty.clone());
+    }
+
+    match generic_arg(ty) {
+        Some((ref name, Some(inner))) if is_one_of(name, ARC_PTR_PATHS) => {
+            Ret::ArcPtr(inner.clone())
+        }
+        Some((ref name, Some(inner))) if is_one_of(name, ARC_PATHS) => Ret::Arc(inner.clone()),
+        Some((ref name, Some(inner))) if is_one_of(name, VEC_PATHS) => Ret::Vec(inner.clone()),
+        _ => Ret::Other(ty.clone()),
+    }
+}
+
+/// Rejects the standard library's `Vec<T>`, which has no FFI-safe layout.
+fn reject_unshareable(ty: &Type) -> syn::Result<()> {
+    match generic_arg(ty) {
+        Some((ref name, Some(_))) if is_one_of(name, STD_VEC_PATHS) => Err(syn::Error::new(
+            ty.span(),
+            "`std::vec::Vec<T>` cannot be shared with C, use `cursed::vec::Vec<T>` instead",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Splits `Result<T, E>` into `T`, or returns `None` for any other type.
+fn result_ok_type(ty: &Type) -> Option<&Type> {
+    match generic_arg(ty) {
+        Some((ref name, Some(ok))) if is_one_of(name, RESULT_PATHS) => Some(ok),
+        _ => None,
+    }
+}
+
+impl Ret {
+    fn ffi_type(&self) -> TokenStream2 {
+        match self {
+            Ret::Unit => quote!(()),
+            Ret::Primitive(ty) => quote!(#ty),
+            Ret::ArcPtr(ty) | Ret::Arc(ty) | Ret::Other(ty) => quote!(::cursed::sync::ArcPtr<#ty>),
+            Ret::Vec(ty) => quote!(::cursed::nullable::Nullable<::cursed::vec::Vec<#ty>>),
+        }
+    }
+
+    fn fallback(&self) -> TokenStream2 {
+        match self {
+            Ret::Unit => quote!(()),
+            Ret::Primitive(_) => quote!(::core::default::Default::default()),
+            Ret::ArcPtr(_) | Ret::Arc(_) | Ret::Other(_) => quote!(::cursed::sync::ArcPtr::null()),
+            Ret::Vec(_) => quote!(::cursed::nullable::null()),
+        }
+    }
+
+    fn convert(&self, value: TokenStream2) -> TokenStream2 {
+        match self {
+            Ret::Unit | Ret::Primitive(_) => value,
+            Ret::ArcPtr(_) => value,
+            Ret::Arc(_) | Ret::Other(_) => quote!(::cursed::sync::ArcPtr::from(#value)),
+            Ret::Vec(_) => quote!(::cursed::nullable::Nullable::new(#value.into_raw())),
+        }
+    }
+}
+
+fn expand(item: ItemFn) -> syn::Result<TokenStream2> {
+    let sig = &item.sig;
+
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            sig.generics.span(),
+            "exported functions cannot be generic",
+        ));
+    }
+    if sig.asyncness.is_some() || sig.variadic.is_some() {
+        return Err(syn::Error::new(
+            sig.span(),
+            "exported functions cannot be async or variadic",
+        ));
+    }
+
+    let name = &sig.ident;
+    let attrs = &item.attrs;
+    let exception = format_ident!("__cursed_exception");
+
+    let (ret, fallible) = match &sig.output {
+        ReturnType::Default => (Ret::Unit, false),
+        ReturnType::Type(_, ty) => match result_ok_type(ty) {
+            Some(ok) => (reject_unshareable(ok).map(|_| classify_ret(ok))?, true),
+            None => (reject_unshareable(ty).map(|_| classify_ret(ty))?, false),
+        },
+    };
+    let ret_ty = ret.ffi_type();
+    let fallback = ret.fallback();
+
+    let mut params = vec![];
+    let mut conversions = vec![];
+    let mut call_args = vec![];
+
+    for input in sig.inputs.iter() {
+        let pat_ty = match input {
+            FnArg::Typed(pat_ty) => pat_ty,
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new(
+                    receiver.span(),
+                    "exported functions cannot take self",
+                ))
+            }
+        };
+        let ident = match &*pat_ty.pat {
+            Pat::Ident(pat) => pat.ident.clone(),
+            pat => {
+                return Err(syn::Error::new(
+                    pat.span(),
+                    "exported function arguments must be identifiers",
+                ))
+            }
+        };
+
+        reject_unshareable(&pat_ty.ty)?;
+        let (param, conversion) = match classify_arg(&pat_ty.ty) {
+            Arg::Ref(ty) => (
+                quote!(#ident: ::cursed::inout::In<#ty>),
+                quote!(let #ident = unsafe { ::cursed::try_as_ref!(#ident, &#exception, #fallback) };),
+            ),
+            Arg::RefMut(ty) => (
+                quote!(mut #ident: ::cursed::inout::InOut<#ty>),
+                quote!(let #ident = unsafe { ::cursed::try_as_mut_ref!(#ident, &#exception, #fallback) };),
+            ),
+            Arg::Str => (
+                quote!(#ident: ::cursed::inout::In<::cursed::libc::c_char>),
+                quote!(let #ident = ::cursed::try_as_str!(#ident, &#exception, #fallback);),
+            ),
+            Arg::Slice(ty) => (
+                quote!(#ident: ::cursed::inout::InSlice<#ty>),
+                quote!(let #ident = unsafe { ::cursed::try_as_slice!(#ident, &#exception, #fallback) };),
+            ),
+            Arg::SliceMut(ty) => (
+                quote!(mut #ident: ::cursed::inout::OutSlice<#ty>),
+                quote!(let #ident = unsafe { ::cursed::try_as_mut_slice!(#ident, &#exception, #fallback) };),
+            ),
+            Arg::ArcPtr(ty) => (
+                quote!(#ident: ::cursed::inout::In<::cursed::sync::ArcPtr<#ty>>),
+                quote!(let #ident = ::cursed::try_as_arc!(#ident, &#exception, #fallback);),
+            ),
+            Arg::Arc(ty) => (
+                quote!(#ident: ::cursed::inout::In<::cursed::sync::ArcPtr<#ty>>),
+                quote!(let #ident = ::cursed::try_as_arc!(#ident, &#exception, #fallback).into_arc();),
+            ),
+            Arg::Vec(ty) => (
+                quote!(#ident: ::cursed::inout::In<::cursed::vec::Vec<#ty>>),
+                quote!(let #ident = unsafe { ::cursed::try_as_ref!(#ident, &#exception, #fallback) }.clone();),
+            ),
+            Arg::Value(ty) => (quote!(#ident: #ty), quote!()),
+        };
+
+        params.push(param);
+        conversions.push(conversion);
+        call_args.push(ident);
+    }
+
+    let call = quote!(#name(#(#call_args),*));
+    let body = if fallible {
+        let ok = ret.convert(quote!(value));
+        quote! {
+            match #call {
+                Ok(value) => #ok,
+                Err(e) => {
+                    let _: ::cursed::nullable::Nullable<()> = ::cursed::exception::throw(e, &#exception);
+                    #fallback
+                }
+            }
+        }
+    } else {
+        ret.convert(call)
+    };
+
+    let inner = ItemFn {
+        attrs: vec![],
+        vis: syn::Visibility::Inherited,
+        sig: item.sig.clone(),
+        block: item.block.clone(),
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #[no_mangle]
+        pub extern "C" fn #name(
+            #(#params,)*
+            #exception: ::cursed::inout::OutPtr<::cursed::exception::Exception>
+        ) -> #ret_ty {
+            #inner
+
+            ::cursed::ffi_boundary!(&#exception, #fallback, {
+                #(#conversions)*
+                #body
+            })
+        }
+    })
+}
+
+/// Generates a `#[no_mangle] extern "C"` shim for a plain Rust function.
+///
+/// Arguments of type `&T`, `&mut T`, `&str`, `&[T]`, `&mut [T]`, `ArcPtr<T>`, `Arc<T>` and
+/// `cursed::vec::Vec<T>` are accepted as `In<T>`, `InOut<T>`, `In<c_char>`, `InSlice<T>`,
+/// `OutSlice<T>`, `In<ArcPtr<T>>`, `In<ArcPtr<T>>` and `In<Vec<T>>` respectively, and null
+/// checked. Any other argument type is passed through unchanged.
+///
+/// Primitives and `()` are returned by value; `ArcPtr<T>` and any other type are returned as
+/// a nullable `ArcPtr<T>`, and `cursed::vec::Vec<T>` as an owned `Nullable<Vec<T>>`. A returned
+/// `Result<T, E>` is unwrapped, with the error reported through the trailing
+/// `OutPtr<Exception>` argument that is appended to every shim.
+///
+/// An `Arc<T>` cannot share its allocation with C, so `T` must be `Clone` for the value to be
+/// copied to or from an `ArcPtr<T>`; prefer `ArcPtr<T>` to share it instead.
+///
+/// `std::vec::Vec<T>` has no FFI-safe layout and is rejected; a bare `Vec<T>` is taken to be
+/// `cursed::vec::Vec<T>`.
+///
+/// The annotated function is moved inside the shim, which takes its name.
+#[proc_macro_attribute]
+pub fn export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            proc_macro2::TokenStream::from(attr).span(),
+            "export takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let item = parse_macro_input!(item as ItemFn);
+    match expand(item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn args() {
+        assert!(matches!(classify_arg(&parse_quote!(&Foo)), Arg::Ref(_)));
+        assert!(matches!(
+            classify_arg(&parse_quote!(&mut Foo)),
+            Arg::RefMut(_)
+        ));
+        assert!(matches!(classify_arg(&parse_quote!(&str)), Arg::Str));
+        assert!(matches!(classify_arg(&parse_quote!(&[u8])), Arg::Slice(_)));
+        assert!(matches!(
+            classify_arg(&parse_quote!(&mut [u8])),
+            Arg::SliceMut(_)
+        ));
+        assert!(matches!(
+            classify_arg(&parse_quote!(ArcPtr<Foo>)),
+            Arg::ArcPtr(_)
+        ));
+        assert!(matches!(
+            classify_arg(&parse_quote!(cursed::vec::Vec<u8>)),
+            Arg::Vec(_)
+        ));
+        assert!(matches!(
+            classify_arg(&parse_quote!(::cursed::sync::ArcPtr<Foo>)),
+            Arg::ArcPtr(_)
+        ));
+        assert!(matches!(classify_arg(&parse_quote!(Vec<u8>)), Arg::Vec(_)));
+        assert!(matches!(
+            classify_arg(&parse_quote!(other::Vec<u8>)),
+            Arg::Value(_)
+        ));
+        assert!(matches!(classify_arg(&parse_quote!(u32)), Arg::Value(_)));
+    }
+
+    #[test]
+    fn returns() {
+        let ty: Type = parse_quote!(Result<ArcPtr<Foo>, Error>);
+        assert!(matches!(
+            classify_ret(result_ok_type(&ty).unwrap()),
+            Ret::ArcPtr(_)
+        ));
+        assert!(matches!(classify_ret(&parse_quote!(())), Ret::Unit));
+        assert!(matches!(
+            classify_ret(&parse_quote!(u64)),
+            Ret::Primitive(_)
+        ));
+        assert!(matches!(classify_ret(&parse_quote!(Foo)), Ret::Other(_)));
+        assert!(result_ok_type(&parse_quote!(Foo)).is_none());
+    }
+
+    #[test]
+    fn rejects_generics() {
+        let item: ItemFn = parse_quote!(
+            fn foo<T>(x: T) {}
+        );
+        assert!(expand(item).is_err());
+    }
+
+    #[test]
+    fn rejects_std_vec() {
+        let item: ItemFn = parse_quote!(
+            fn foo(x: std::vec::Vec<u8>) {}
+        );
+        assert!(expand(item).is_err());
+        let item: ItemFn = parse_quote!(
+            fn foo() -> ::alloc::vec::Vec<u8> {}
+        );
+        assert!(expand(item).is_err());
+    }
+
+    #[test]
+    fn converts_std_arc() {
+        assert!(matches!(classify_arg(&parse_quote!(Arc<Foo>)), Arg::Arc(_)));
+        let ty: Type = parse_quote!(Result<std::sync::Arc<Foo>, Error>);
+        assert!(matches!(
+            classify_ret(result_ok_type(&ty).unwrap()),
+            Ret::Arc(_)
+        ));
+        let item: ItemFn = parse_quote!(
+            fn foo(x: Arc<Foo>) -> Arc<Foo> {}
+        );
+        assert!(expand(item).is_ok());
+    }
+}
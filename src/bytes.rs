@@ -0,0 +1,386 @@
+//! An immutable byte buffer for binary payloads, stored contiguously rather than as a `Vec<u8>`.
+//!
+//! A `Bytes` is either copied from Rust or C, or adopts a buffer allocated by C, which is released
+//! with the caller's callback once no `Bytes` refers to it. Slicing and cloning share the buffer.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec as RealVec};
+use core::ffi::c_void;
+use core::fmt;
+use core::ops::{Bound, Deref, RangeBounds};
+use core::ptr::NonNull;
+
+use crate::{
+    exception::{throw_kind, throw_null, Exception, ExceptionKind},
+    inout::{In, InSlice, OutPtr},
+    nullable::Nullable,
+};
+
+/// Releases a buffer adopted by `bytes_from_foreign`, given its `user_data`, pointer and length.
+/// As with `Callback`, `user_data` comes first.
+pub type ReleaseFn = extern "C" fn(user_data: *mut c_void, ptr: *mut u8, len: usize);
+
+enum Storage {
+    Owned(Box<[u8]>),
+    Foreign {
+        ptr: *mut u8,
+        len: usize,
+        release: Option<ReleaseFn>,
+        user_data: *mut c_void,
+    },
+}
+
+// As with `Callback`, the buffer's owner is responsible for it being releasable from any thread.
+unsafe impl Send for Storage {}
+unsafe impl Sync for Storage {}
+
+impl Storage {
+    fn as_ptr(&self) -> *const u8 {
+        match self {
+            Storage::Owned(bytes) => bytes.as_ptr(),
+            Storage::Foreign { ptr, .. } => *ptr,
+        }
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        if let Storage::Foreign {
+            ptr,
+            len,
+            release: Some(release),
+            user_data,
+        } = *self
+        {
+            release(user_data, ptr, len);
+        }
+    }
+}
+
+/// A shared, immutable slice of bytes.
+#[derive(Clone)]
+pub struct Bytes {
+    storage: Arc<Storage>,
+    offset: usize,
+    len: usize,
+}
+
+impl Bytes {
+    pub fn new() -> Bytes {
+        Bytes::from(RealVec::new())
+    }
+
+    /// Adopts a buffer allocated by foreign code, which `release` is called with once the last
+    /// `Bytes` referring to it is dropped.
+    ///
+    /// # Safety
+    ///
+    /// Unless `len` is 0, `ptr` must be valid for reads of `len` bytes, which must not change
+    /// until it is released. `release` must be safe to call with `user_data` from any thread.
+    pub unsafe fn from_foreign(
+        ptr: *mut u8,
+        len: usize,
+        release: Option<ReleaseFn>,
+        user_data: *mut c_void,
+    ) -> Bytes {
+        let storage = Storage::Foreign {
+            ptr,
+            len,
+            release,
+            user_data,
+        };
+        Bytes {
+            storage: Arc::new(storage),
+            offset: 0,
+            len,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        match self.len {
+            // A foreign buffer may be null when empty, which a slice may not be.
+            0 => NonNull::dangling().as_ptr(),
+            _ => unsafe { self.storage.as_ptr().add(self.offset) },
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the bytes in `range` without copying them, or `None` if it is out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Option<Bytes> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1)?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+        if start > end || end > self.len {
+            return None;
+        }
+        Some(Bytes {
+            storage: Arc::clone(&self.storage),
+            offset: self.offset + start,
+            len: end - start,
+        })
+    }
+
+    pub fn to_vec(&self) -> RealVec<u8> {
+        self.as_slice().to_vec()
+    }
+
+    pub fn into_raw(self) -> *const Bytes {
+        let ptr = Box::into_raw(Box::new(self)) as *const _;
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "Bytes", "[u8]");
+        ptr
+    }
+
+    /// Reclaims a buffer previously returned by `into_raw` or one of the `bytes_` exports.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` and must not be used again once reclaimed.
+    pub unsafe fn from_raw(ptr: *const Bytes) -> Bytes {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr);
+        *Box::from_raw(ptr as *mut Bytes)
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Bytes {
+        Bytes::new()
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Bytes {}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Bytes").field(&self.as_slice()).finish()
+    }
+}
+
+impl From<RealVec<u8>> for Bytes {
+    fn from(vec: RealVec<u8>) -> Bytes {
+        let len = vec.len();
+        Bytes {
+            storage: Arc::new(Storage::Owned(vec.into_boxed_slice())),
+            offset: 0,
+            len,
+        }
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(slice: &[u8]) -> Bytes {
+        Bytes::from(slice.to_vec())
+    }
+}
+
+/// Copies `data` into a new buffer. Free with `bytes_free`.
+#[no_mangle]
+pub extern "C" fn bytes_new(data: InSlice<u8>, exception: OutPtr<Exception>) -> Nullable<Bytes> {
+    ffi_boundary!(&exception, {
+        let data = unsafe { try_as_slice!(data, &exception) };
+        Nullable::from_owned_raw(Bytes::from(data).into_raw())
+    })
+}
+
+/// Adopts `len` bytes at `ptr` without copying them. Once the last buffer referring to them is
+/// freed, `release` is called with `user_data`, `ptr` and `len`, from whichever thread frees it.
+///
+/// If an exception is thrown, the bytes are not adopted and `release` is not called.
+// The requirements of `Bytes::from_foreign` are left to the caller, as they are for every export
+// taking a pointer.
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn bytes_from_foreign(
+    ptr: *mut u8,
+    len: usize,
+    release: Option<ReleaseFn>,
+    user_data: *mut c_void,
+    exception: OutPtr<Exception>,
+) -> Nullable<Bytes> {
+    ffi_boundary!(&exception, {
+        if ptr.is_null() && len > 0 {
+            return throw_null("ptr", &exception);
+        }
+        let bytes = unsafe { Bytes::from_foreign(ptr, len, release, user_data) };
+        Nullable::from_owned_raw(bytes.into_raw())
+    })
+}
+
+/// Borrows the bytes, which remain valid until `bytes` is freed.
+#[no_mangle]
+pub extern "C" fn bytes_ptr(bytes: In<Bytes>, exception: OutPtr<Exception>) -> *const u8 {
+    ffi_boundary!(&exception, core::ptr::null(), {
+        let bytes = unsafe { try_as_ref!(bytes, &exception, core::ptr::null()) };
+        bytes.as_ptr()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn bytes_len(bytes: In<Bytes>, exception: OutPtr<Exception>) -> usize {
+    ffi_boundary!(&exception, 0, {
+        let bytes = unsafe { try_as_ref!(bytes, &exception, 0) };
+        bytes.len()
+    })
+}
+
+/// Returns `len` bytes from `start` without copying them. Free with `bytes_free`.
+#[no_mangle]
+pub extern "C" fn bytes_slice(
+    bytes: In<Bytes>,
+    start: usize,
+    len: usize,
+    exception: OutPtr<Exception>,
+) -> Nullable<Bytes> {
+    ffi_boundary!(&exception, {
+        let bytes = unsafe { try_as_ref!(bytes, &exception) };
+        let slice = start
+            .checked_add(len)
+            .and_then(|end| bytes.slice(start..end));
+        match slice {
+            Some(slice) => Nullable::from_owned_raw(slice.into_raw()),
+            None => throw_kind(
+                ExceptionKind::OutOfBounds,
+                alloc::format!(
+                    "{} bytes from {} is out of bounds for a buffer of length {}",
+                    len,
+                    start,
+                    bytes.len()
+                ),
+                &exception,
+            ),
+        }
+    })
+}
+
+/// Returns another reference to the same bytes. Free with `bytes_free`.
+#[no_mangle]
+pub extern "C" fn bytes_clone(bytes: In<Bytes>, exception: OutPtr<Exception>) -> Nullable<Bytes> {
+    ffi_boundary!(&exception, {
+        let bytes = unsafe { try_as_ref!(bytes, &exception) };
+        Nullable::from_owned_raw(bytes.clone().into_raw())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn bytes_free(bytes: *mut Bytes) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        if let Some(bytes) = NonNull::new(bytes) {
+            drop(unsafe { Bytes::from_raw(bytes.as_ptr()) });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn slices() {
+        let bytes = Bytes::from(&b"hello world"[..]);
+        let world = bytes.slice(6..).unwrap();
+        assert_eq!(&*world, b"world");
+        assert_eq!(world.as_ptr(), unsafe { bytes.as_ptr().add(6) });
+        assert_eq!(&*world.slice(1..=2).unwrap(), b"or");
+        assert!(world.slice(..6).is_none());
+        assert!(world.slice(5..).unwrap().is_empty());
+        assert_eq!(Bytes::new(), Bytes::from(RealVec::new()));
+    }
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn release(user_data: *mut c_void, ptr: *mut u8, len: usize) {
+        assert_eq!(user_data as usize, 42);
+        drop(unsafe { RealVec::from_raw_parts(ptr, len, len) });
+        RELEASED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn ffi() {
+        let mut buf = core::mem::ManuallyDrop::new(b"payload".to_vec().into_boxed_slice());
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        let foreign =
+            bytes_from_foreign(ptr, len, Some(release), 42 as *mut c_void, OutPtr::null());
+        let foreign = foreign.into_raw() as *mut Bytes;
+        assert_eq!(
+            bytes_ptr(In::from(unsafe { &*foreign }), OutPtr::null()),
+            ptr as *const u8
+        );
+
+        let slice = bytes_slice(In::from(unsafe { &*foreign }), 3, 4, OutPtr::null());
+        let slice = slice.into_raw() as *mut Bytes;
+        bytes_free(foreign);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+        assert_eq!(bytes_len(In::from(unsafe { &*slice }), OutPtr::null()), 4);
+        assert_eq!(&**unsafe { &*slice }, b"load");
+        bytes_free(slice);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+
+        let mut raw = core::ptr::null_mut();
+        let copied = bytes_new(InSlice::from(&b"abc"[..]), OutPtr::null()).into_raw();
+        let handle = In::from(unsafe { &*copied });
+        assert!(bytes_slice(handle, 2, 2, OutPtr::from(&mut raw)).is_null());
+        let e = unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::OutOfBounds);
+        bytes_free(copied as *mut Bytes);
+
+        let empty = bytes_from_foreign(
+            core::ptr::null_mut(),
+            0,
+            None,
+            core::ptr::null_mut(),
+            OutPtr::null(),
+        );
+        let empty = empty.into_raw() as *mut Bytes;
+        assert!(unsafe { &*empty }.is_empty());
+        bytes_free(empty);
+        assert!(bytes_from_foreign(
+            core::ptr::null_mut(),
+            1,
+            None,
+            core::ptr::null_mut(),
+            OutPtr::from(&mut raw)
+        )
+        .is_null());
+        let e = unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::NullArgument);
+    }
+}
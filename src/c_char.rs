@@ -1,8 +1,26 @@
+use alloc::borrow::Cow;
 use alloc::boxed::Box;
 use alloc::string::String;
-use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::ptr::NonNull;
+use core::str::Utf8Error;
 
+use crate::exception::Exception;
+use crate::inout::{In, OutPtr};
+use crate::sync::ArcPtr;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "no-std")] {
+        use crate::vendor::c_str::{CStr, CString};
+    } else if #[cfg(not(feature = "no-std"))] {
+        use std::ffi::{CStr, CString};
+    }
+}
+
+/// An owned, NUL-terminated string for returning over the FFI, released with
+/// `cursed_string_free`.
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct CCharPtr(*mut libc::c_char);
@@ -13,23 +31,191 @@ impl CCharPtr {
         unsafe { libc::strlen(self.0) }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn as_ptr(&self) -> *mut libc::c_char {
         self.0
     }
-    
-    pub fn as_str<'a>(&'a self) -> &'a str {
+
+    /// The bytes of the string, not including the NUL byte.
+    pub fn as_bytes(&self) -> &[u8] {
         // Safe because pointer is always a valid C string.
-        let slice = unsafe { core::slice::from_raw_parts(self.as_ptr() as *const u8, self.len()) };
+        unsafe { core::slice::from_raw_parts(self.as_ptr() as *const u8, self.len()) }
+    }
+
+    /// The string, if it is valid UTF-8. As a `CCharPtr` may be reclaimed from foreign code with
+    /// `from_raw`, this is not assumed.
+    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
+
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.as_bytes())
+    }
+
+    /// Releases the string to the caller, to be freed with `cursed_string_free`.
+    pub fn into_raw(self) -> *mut libc::c_char {
+        let ptr = self.0;
+        core::mem::forget(self);
+        ptr
+    }
 
-        // Safe because pointer is always a valid UTF8 string.
-        unsafe { core::str::from_utf8_unchecked(slice) }
+    /// Reclaims a string previously released with `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` and must not have been freed already.
+    pub unsafe fn from_raw(ptr: NonNull<libc::c_char>) -> CCharPtr {
+        CCharPtr(ptr.as_ptr())
     }
 }
 
 impl Drop for CCharPtr {
     fn drop(&mut self) {
         log::debug!("Drop: {:?}", self.0);
-        unsafe { Box::from_raw(self.0) };
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(self.0);
+        drop(unsafe { CString::from_raw(self.0) });
+    }
+}
+
+impl From<CString> for CCharPtr {
+    fn from(string: CString) -> CCharPtr {
+        let ptr = string.into_raw();
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "CCharPtr", "str");
+        CCharPtr(ptr)
+    }
+}
+
+/// An owned string and its length in bytes, for strings that may contain interior NULs. It is
+/// also NUL-terminated, which is not counted in `len`. Released with `cursed_string_buf_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct CCharBuf {
+    pub ptr: *mut libc::c_char,
+    pub len: usize,
+}
+
+impl CCharBuf {
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for CCharBuf {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            let slice = core::ptr::slice_from_raw_parts_mut(self.ptr as *mut u8, self.len + 1);
+            drop(unsafe { Box::from_raw(slice) });
+        }
+    }
+}
+
+impl From<Vec<u8>> for CCharBuf {
+    fn from(mut bytes: Vec<u8>) -> CCharBuf {
+        let len = bytes.len();
+        bytes.push(b'\0');
+        CCharBuf {
+            ptr: Box::into_raw(bytes.into_boxed_slice()) as *mut libc::c_char,
+            len,
+        }
+    }
+}
+
+/// A shared, immutable, NUL-terminated UTF-8 string.
+///
+/// It is given to C as a `const char*` that is also an `ArcPtr`, so that foreign owners may share
+/// it with `arc_clone` or `cursed_arc_c_str_clone`. Each reference is released with
+/// `cursed_arc_c_str_drop` or `arc_drop`.
+#[derive(Clone)]
+pub struct ArcCStr(ArcPtr<[u8]>);
+
+impl ArcCStr {
+    pub fn new<S: Into<String>>(string: S) -> Result<ArcCStr, NulError> {
+        let mut inner = string.into().into_bytes();
+
+        if let Some(i) = memchr::memchr(0, &inner) {
+            return Err(NulError(i, inner));
+        }
+
+        inner.push(0);
+        Ok(ArcCStr(ArcPtr::from(inner)))
+    }
+
+    pub fn as_c_str(&self) -> &CStr {
+        // Safe because it has exactly one NUL, at the end.
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.0) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Safe because it is always constructed from a `str`.
+        unsafe { core::str::from_utf8_unchecked(&self.0[..self.0.len() - 1]) }
+    }
+
+    pub fn as_ptr(&self) -> *const libc::c_char {
+        self.0.as_ptr() as *const libc::c_char
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.0.strong_count()
+    }
+
+    /// Releases this reference to the caller as a thin pointer to the string's bytes.
+    pub fn into_raw(self) -> *const libc::c_char {
+        self.0.into_raw() as *const libc::c_char
+    }
+
+    /// Reclaims a reference previously released with `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` or `cursed_arc_c_str_new`, and the reference it holds must
+    /// not have been released already.
+    pub unsafe fn from_raw(ptr: NonNull<libc::c_char>) -> ArcCStr {
+        ArcCStr(ArcPtr::from_raw_thin(ptr.as_ptr() as *const u8))
+    }
+}
+
+impl PartialEq for ArcCStr {
+    fn eq(&self, other: &ArcCStr) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for ArcCStr {}
+
+impl core::hash::Hash for ArcCStr {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl core::ops::Deref for ArcCStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for ArcCStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for ArcCStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<ArcCStr> for ArcPtr<libc::c_char> {
+    fn from(string: ArcCStr) -> ArcPtr<libc::c_char> {
+        unsafe { ArcPtr::from_raw(string.into_raw()) }
     }
 }
 
@@ -37,77 +223,97 @@ impl Drop for CCharPtr {
 pub struct NulError(usize, Vec<u8>);
 
 pub trait StringExt {
-    fn into_c_char(self) -> Result<*mut libc::c_char, NulError>;
-    fn into_arc_c_char(self) -> Result<Arc<libc::c_char>, NulError>;
+    fn into_c_char(self) -> Result<CCharPtr, NulError>;
+    fn into_c_char_buf(self) -> CCharBuf;
+    fn into_arc_c_char(self) -> Result<ArcCStr, NulError>;
 }
 
 impl StringExt for String {
-    fn into_c_char(self) -> Result<*mut libc::c_char, NulError> {
-        let mut inner = self.into_bytes();
+    fn into_c_char(self) -> Result<CCharPtr, NulError> {
+        let inner = self.into_bytes();
 
         if let Some(i) = memchr::memchr(0, &inner) {
             return Err(NulError(i, inner));
         }
 
-        inner.push(b'\0');
-        let p = Box::into_raw(
-            inner.into_boxed_slice()
-        ) as *mut libc::c_char;
-        Ok(p)
-        
-        // Ok(CCharPtr(p))
-
-        
-        // Ok(unsafe {
-        //     core::mem::transmute::<*mut libc::c_char, CCharPtr>(
-        //         {
-        //             let p = Box::into_raw(
-        //                 inner.into_boxed_slice()
-        //             ) as *mut libc::c_char;
-
-        //             log::debug!("SIN!: {:?}", p);
-        //             p
-        //         }
-        //     )
-        // })
-    }
-
-    fn into_arc_c_char(self) -> Result<Arc<*mut libc::c_char>, NulError> {
-        Ok(Arc::new(self.into_c_char()?))
-        
-        // Ok(unsafe {
-        //     core::mem::transmute::<*mut libc::c_char, CCharPtr>(
-        //         {
-        //             let p = Box::into_raw(Arc::new(
-        //                 inner.into_boxed_slice()
-        //             ) as *mut libc::c_char;
-
-        //             log::debug!("SIN!: {:?}", p);
-        //             p
-        //         }
-        //     )
-        // })
-    }
-}
-
-impl<'a> From<&'a CCharPtr> for &'a str {
-    fn from(c_char_ptr: &'a CCharPtr) -> &'a str {
+        let string = unsafe { CString::from_vec_unchecked(inner) };
+        Ok(CCharPtr::from(string))
+    }
+
+    fn into_c_char_buf(self) -> CCharBuf {
+        CCharBuf::from(self.into_bytes())
+    }
+
+    fn into_arc_c_char(self) -> Result<ArcCStr, NulError> {
+        ArcCStr::new(self)
+    }
+}
+
+impl<'a> TryFrom<&'a CCharPtr> for &'a str {
+    type Error = Utf8Error;
+
+    fn try_from(c_char_ptr: &'a CCharPtr) -> Result<&'a str, Utf8Error> {
         c_char_ptr.as_str()
     }
 }
 
-impl From<CCharPtr> for String {
-    fn from(c_char_ptr: CCharPtr) -> String {
-        String::from(c_char_ptr.as_str())
+impl TryFrom<CCharPtr> for String {
+    type Error = Utf8Error;
+
+    fn try_from(c_char_ptr: CCharPtr) -> Result<String, Utf8Error> {
+        c_char_ptr.as_str().map(String::from)
+    }
+}
+
+/// Frees a string returned as a `CCharPtr`.
+#[no_mangle]
+pub extern "C" fn cursed_string_free(ptr: *mut libc::c_char) {
+    if let Some(ptr) = NonNull::new(ptr) {
+        drop(unsafe { CCharPtr::from_raw(ptr) });
+    }
+}
+
+/// Copies a string into a new `ArcCStr`, throwing if it is not valid UTF-8.
+#[no_mangle]
+pub extern "C" fn cursed_arc_c_str_new(
+    string: In<libc::c_char>,
+    exception: OutPtr<Exception>,
+) -> ArcPtr<libc::c_char> {
+    ffi_boundary!(&exception, ArcPtr::null(), {
+        let string = try_as_str!(string, &exception, ArcPtr::null());
+        // Cannot fail, as it was read up to its first NUL.
+        ArcPtr::from(ArcCStr::new(string).unwrap())
+    })
+}
+
+/// Adds a reference to an `ArcCStr`, returning the same pointer.
+#[no_mangle]
+pub extern "C" fn cursed_arc_c_str_clone(string: *const libc::c_char) -> *const libc::c_char {
+    if let Some(string) = NonNull::new(string as *mut libc::c_char) {
+        // The caller keeps its own reference, so it must not be released here.
+        let string = core::mem::ManuallyDrop::new(unsafe { ArcCStr::from_raw(string) });
+        core::mem::forget(ArcCStr::clone(&string));
     }
+    string
+}
+
+/// Releases one reference to an `ArcCStr`, freeing it if it was the last.
+#[no_mangle]
+pub extern "C" fn cursed_arc_c_str_drop(string: *const libc::c_char) {
+    if let Some(string) = NonNull::new(string as *mut libc::c_char) {
+        drop(unsafe { ArcCStr::from_raw(string) });
+    }
+}
+
+/// Frees a string returned as a `CCharBuf`.
+#[no_mangle]
+pub extern "C" fn cursed_string_buf_free(buf: CCharBuf) {
+    drop(buf);
 }
 
 #[cfg(test)]
 mod tests {
-    use super::CCharPtr;
-    use super::StringExt;
-    use alloc::string::String;
-    use alloc::boxed::Box;
+    use super::*;
 
     #[test]
     fn size() {
@@ -128,4 +334,53 @@ mod tests {
         let x = s.into_arc_c_char();
         assert_eq!(x.unwrap().as_str(), &*t)
     }
+
+    #[test]
+    fn shared_with_arc_clone() {
+        let s = ArcCStr::new("shared").unwrap();
+        let raw = s.clone().into_raw();
+        let cloned = crate::sync::arc_clone(unsafe { ArcPtr::from_raw(raw as *const _) });
+        assert_eq!(cloned.into_raw() as *const libc::c_char, raw);
+        assert_eq!(cursed_arc_c_str_clone(raw), raw);
+        assert_eq!(s.strong_count(), 4);
+
+        cursed_arc_c_str_drop(raw);
+        cursed_arc_c_str_drop(raw);
+        cursed_arc_c_str_drop(raw);
+        assert_eq!(s.strong_count(), 1);
+        assert_eq!(&*s, "shared");
+    }
+
+    #[test]
+    fn arc_c_str_from_c() {
+        let bytes = b"from c\0";
+        let string = In::from(&bytes[0]).cast();
+        let raw = cursed_arc_c_str_new(string, OutPtr::null()).into_raw();
+        let s = unsafe { ArcCStr::from_raw(NonNull::new(raw as *mut _).unwrap()) };
+        assert_eq!(s.as_str(), "from c");
+
+        let bytes = [0xffu8, 0];
+        let mut exception = core::ptr::null_mut();
+        let string = In::from(&bytes[0]).cast();
+        assert!(cursed_arc_c_str_new(string, OutPtr::from(&mut exception)).is_null());
+        let e = unsafe { Exception::from_raw(NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), crate::exception::ExceptionKind::InvalidUtf8);
+    }
+
+    #[test]
+    fn round_trip() {
+        let s = String::from("this is a sample").into_c_char().unwrap();
+        assert_eq!(s.len(), 16);
+        let s = unsafe { CCharPtr::from_raw(NonNull::new(s.into_raw()).unwrap()) };
+        assert_eq!(String::try_from(s).unwrap(), "this is a sample");
+
+        let invalid = unsafe { CCharPtr::from(CString::from_vec_unchecked(alloc::vec![b'a', 0xff])) };
+        assert!(invalid.as_str().is_err());
+        assert_eq!(invalid.to_string_lossy(), "a\u{fffd}");
+
+        let buf = String::from("this is a\0 sample").into_c_char_buf();
+        assert_eq!(buf.len, 17);
+        assert_eq!(buf.as_bytes(), b"this is a\0 sample");
+        assert_eq!(unsafe { *buf.ptr.add(buf.len) }, 0);
+    }
 }
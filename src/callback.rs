@@ -0,0 +1,129 @@
+use alloc::boxed::Box;
+use core::ffi::c_void;
+use core::fmt;
+
+use crate::inout::In;
+
+/// A C function pointer with its `user_data`, taking one argument of type `A`.
+///
+/// A `Callback` owns its `user_data`, calling `release` with it when dropped. Use a `#[repr(C)]`
+/// struct for `A` to pass more than one value.
+#[repr(C)]
+pub struct Callback<A, R> {
+    pub callback: extern "C" fn(user_data: *mut c_void, arg: A) -> R,
+    pub user_data: *mut c_void,
+    pub release: Option<extern "C" fn(user_data: *mut c_void)>,
+}
+
+type BoxedFn<A, R> = Box<dyn Fn(A) -> R + Send + Sync>;
+
+extern "C" fn call_boxed<A, R>(user_data: *mut c_void, arg: A) -> R {
+    let f = unsafe { &*(user_data as *const BoxedFn<A, R>) };
+    f(arg)
+}
+
+extern "C" fn release_boxed<A, R>(user_data: *mut c_void) {
+    drop(unsafe { Box::from_raw(user_data as *mut BoxedFn<A, R>) });
+}
+
+impl<A, R> Callback<A, R> {
+    /// Wraps a Rust closure so that it may be handed to C.
+    pub fn from_fn<F>(f: F) -> Callback<A, R>
+    where
+        F: Fn(A) -> R + Send + Sync + 'static,
+    {
+        let boxed: Box<BoxedFn<A, R>> = Box::new(Box::new(f));
+        Callback {
+            callback: call_boxed::<A, R>,
+            user_data: Box::into_raw(boxed) as *mut c_void,
+            release: Some(release_boxed::<A, R>),
+        }
+    }
+
+    pub fn call(&self, arg: A) -> R {
+        (self.callback)(self.user_data, arg)
+    }
+}
+
+impl<A, R> Drop for Callback<A, R> {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            release(self.user_data);
+        }
+    }
+}
+
+impl<A, R> fmt::Debug for Callback<A, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Callback")
+            .field("user_data", &self.user_data)
+            .field("release", &self.release.is_some())
+            .finish()
+    }
+}
+
+// The callback's author is responsible for its user data being usable from any thread, as it is
+// for any value handed to cursed.
+unsafe impl<A, R> Send for Callback<A, R> {}
+unsafe impl<A, R> Sync for Callback<A, R> {}
+
+impl<A, R> In<Callback<A, R>> {
+    /// Takes ownership of the callback, if the pointer is not null.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be null or point to a valid `Callback<A, R>`, which the caller must then
+    /// not use or release, as it is now released when the returned `Callback` is dropped.
+    pub unsafe fn take(&self) -> Option<Callback<A, R>> {
+        self.as_ptr().map(|x| core::ptr::read(x.as_ptr()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn closure_round_trip() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let inner = Arc::clone(&counter);
+        let callback = Callback::from_fn(move |x: usize| inner.fetch_add(x, Ordering::SeqCst) + x);
+
+        assert_eq!(callback.call(2), 2);
+        assert_eq!((callback.callback)(callback.user_data, 3), 5);
+        assert_eq!(Arc::strong_count(&counter), 2);
+        drop(callback);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn double(user_data: *mut c_void, x: u32) -> u32 {
+        assert_eq!(user_data as usize, 0x1234);
+        x * 2
+    }
+
+    extern "C" fn release(user_data: *mut c_void) {
+        RELEASED.fetch_add(user_data as usize, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn from_c() {
+        let callback = Callback {
+            callback: double,
+            user_data: 0x1234 as *mut c_void,
+            release: Some(release),
+        };
+
+        let borrowed = In::from(&callback);
+        assert_eq!(unsafe { borrowed.as_ref() }.unwrap().call(21), 42);
+
+        let callback = core::mem::ManuallyDrop::new(callback);
+        let taken = unsafe { In::from(&*callback).take() }.unwrap();
+        assert_eq!(taken.call(1), 2);
+        drop(taken);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0x1234);
+    }
+}
@@ -1,5 +1,9 @@
+use crate::inout::{In, Out, OutPtr};
 use crate::nullable::{null, Nullable};
+use alloc::boxed::Box;
 use alloc::format;
+use alloc::vec::Vec;
+use core::any::Any;
 use core::convert::TryFrom;
 use core::fmt;
 use core::ptr::NonNull;
@@ -13,36 +17,146 @@ cfg_if::cfg_if! {
     }
 }
 
-/// A newtype over a raw, owned CString for providing errors over the FFI.
-#[repr(transparent)]
-pub struct Exception(NonNull<c_char>);
+/// The category of an `Exception`, so foreign callers can branch on what went wrong
+/// without matching on the message.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionKind {
+    /// A user-defined error. The code is chosen by whoever threw it.
+    User = 0,
+    /// A required pointer argument was null.
+    NullArgument = 1,
+    /// A string argument was not valid UTF-8.
+    InvalidUtf8 = 2,
+    /// A value was not of the type the caller claimed it was.
+    TypeMismatch = 3,
+    /// A panic was caught at the FFI boundary.
+    Panic = 4,
+    /// An operation was cancelled before it completed.
+    Cancelled = 5,
+    /// A UTF-16 string argument contained an unpaired surrogate.
+    InvalidUtf16 = 6,
+    /// An argument was misaligned, too long or otherwise unusable.
+    InvalidArgument = 7,
+    /// A handle was stale, already freed or never issued.
+    InvalidHandle = 8,
+    /// A lock was poisoned by a panic while it was held.
+    Poisoned = 9,
+    /// An index was past the end of a collection.
+    OutOfBounds = 10,
+}
+
+impl ExceptionKind {
+    /// Infers the kind for a thrown error from its concrete type.
+    pub fn of(error: &dyn Any) -> ExceptionKind {
+        if error.is::<core::str::Utf8Error>() || error.is::<alloc::string::FromUtf8Error>() {
+            ExceptionKind::InvalidUtf8
+        } else if error.is::<alloc::string::FromUtf16Error>() {
+            ExceptionKind::InvalidUtf16
+        } else if error.is::<crate::vec::OutOfBounds>() {
+            ExceptionKind::OutOfBounds
+        } else {
+            ExceptionKind::User
+        }
+    }
+}
+
+/// An owned error for providing failures over the FFI.
+///
+/// Carries a kind, a numeric code (which defaults to the kind's discriminant), a
+/// NUL-terminated message and an optional cause.
+pub struct Exception {
+    kind: ExceptionKind,
+    code: i32,
+    message: CString,
+    cause: Option<Box<Exception>>,
+}
 
 impl Exception {
-    pub unsafe fn from_raw(ptr: NonNull<c_char>) -> Exception {
-        Exception(ptr)
+    /// Creates an exception. The message is truncated at its first interior NUL byte, if any.
+    pub fn new<S: AsRef<str>>(kind: ExceptionKind, message: S) -> Exception {
+        let bytes = message.as_ref().as_bytes();
+        let bytes = match memchr::memchr(0, bytes) {
+            Some(i) => &bytes[..i],
+            None => bytes,
+        };
+
+        Exception {
+            kind,
+            code: kind as i32,
+            message: CString::new(Vec::from(bytes)).expect("interior NULs were stripped"),
+            cause: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: i32) -> Exception {
+        self.code = code;
+        self
+    }
+
+    pub fn with_cause(mut self, cause: Exception) -> Exception {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    pub fn kind(&self) -> ExceptionKind {
+        self.kind
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        // Always valid, as the message was constructed from a `str`.
+        self.message.to_str().unwrap_or_default()
+    }
+
+    pub fn cause(&self) -> Option<&Exception> {
+        self.cause.as_deref()
+    }
+
+    /// Reclaims an exception previously released with `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` and must not have been freed already.
+    pub unsafe fn from_raw(ptr: NonNull<Exception>) -> Exception {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr.as_ptr());
+        *Box::from_raw(ptr.as_ptr())
     }
 
     pub fn into_c_string(self) -> CString {
-        let ret = unsafe { CString::from_raw(self.0.as_ptr() as *mut _) };
-        core::mem::forget(self);
-        ret
+        self.message
     }
 
     pub fn as_ptr(&self) -> *const c_char {
-        self.0.as_ptr()
+        self.message.as_ptr()
     }
 
     pub fn into_raw(self) -> *mut Exception {
-        let ret = self.as_ptr() as *mut _;
-        core::mem::forget(self);
-        ret
+        let ptr = Box::into_raw(Box::new(self));
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "Exception", core::any::type_name::<Exception>());
+        ptr
     }
 }
 
-impl Drop for Exception {
-    fn drop(&mut self) {
-        log::debug!("EXCEPTION DROPPED: {:?}", self.0.as_ptr());
-        unsafe { log::debug!("was: {:?}", CString::from_raw(self.0.as_ptr() as *mut _)) };
+impl fmt::Debug for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Exception")
+            .field("kind", &self.kind)
+            .field("code", &self.code)
+            .field("message", &self.message())
+            .field("cause", &self.cause)
+            .finish()
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.message())
     }
 }
 
@@ -50,8 +164,13 @@ impl TryFrom<&str> for Exception {
     type Error = NulError;
 
     fn try_from(string: &str) -> Result<Exception, Self::Error> {
-        let c_str = CString::new(string)?;
-        Ok(Exception(NonNull::new(c_str.into_raw()).unwrap()))
+        let message = CString::new(string)?;
+        Ok(Exception {
+            kind: ExceptionKind::User,
+            code: ExceptionKind::User as i32,
+            message,
+            cause: None,
+        })
     }
 }
 
@@ -62,25 +181,333 @@ impl From<Exception> for CString {
 }
 
 #[inline]
-pub fn throw_message<T, S: AsRef<str>>(
-    msg: S,
-    exception: &crate::inout::OutPtr<Exception>,
-) -> Nullable<T> {
+pub fn raise<T>(e: Exception, exception: &OutPtr<Exception>) -> Nullable<T> {
     if let Some(ptr) = exception.as_ptr() {
-        let msg = Exception::try_from(msg.as_ref()).unwrap();
-        unsafe { *ptr.as_ptr() = msg.into_raw() };
+        unsafe { *ptr.as_ptr() = e.into_raw() };
     }
     null()
 }
 
 #[inline]
-pub fn throw<T>(e: impl fmt::Display, exception: &crate::inout::OutPtr<Exception>) -> Nullable<T> {
-    if let Some(ptr) = exception.as_ptr() {
-        let msg = Exception::try_from(&*format!("{}", e)).unwrap();
-        unsafe { *ptr.as_ptr() = msg.into_raw() };
+pub fn throw_kind<T, S: AsRef<str>>(
+    kind: ExceptionKind,
+    msg: S,
+    exception: &OutPtr<Exception>,
+) -> Nullable<T> {
+    raise(Exception::new(kind, msg), exception)
+}
+
+#[inline]
+pub fn throw_null<T>(field: &str, exception: &OutPtr<Exception>) -> Nullable<T> {
+    throw_kind(
+        ExceptionKind::NullArgument,
+        format!("{} must not be null", field),
+        exception,
+    )
+}
+
+#[inline]
+pub fn throw_message<T, S: AsRef<str>>(msg: S, exception: &OutPtr<Exception>) -> Nullable<T> {
+    throw_kind(ExceptionKind::User, msg, exception)
+}
+
+#[inline]
+pub fn throw<T, E: fmt::Display + 'static>(e: E, exception: &OutPtr<Exception>) -> Nullable<T> {
+    throw_kind(ExceptionKind::of(&e), format!("{}", e), exception)
+}
+
+/// Runs `f`, converting a panic into a `Panic` exception written through `exception` and
+/// returning `fallback()` instead, as unwinding into foreign code is undefined behaviour.
+///
+/// Arguments crossing the FFI are raw pointers, so `f` is asserted to be unwind safe.
+#[cfg(not(feature = "no-std"))]
+pub fn catch_panic<R, D, F>(exception: &OutPtr<Exception>, fallback: D, f: F) -> R
+where
+    D: FnOnce() -> R,
+    F: FnOnce() -> R,
+{
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let msg = match payload.downcast_ref::<&str>() {
+                Some(msg) => *msg,
+                None => match payload.downcast_ref::<alloc::string::String>() {
+                    Some(msg) => msg.as_str(),
+                    None => "Box<Any>",
+                },
+            };
+            let _: Nullable<()> = throw_kind(
+                ExceptionKind::Panic,
+                format!("panicked at '{}'", msg),
+                exception,
+            );
+            fallback()
+        }
     }
-    null()
+}
+
+/// Without `std` there is no unwinding to catch, so this runs `f` directly.
+#[cfg(feature = "no-std")]
+pub fn catch_panic<R, D, F>(_exception: &OutPtr<Exception>, _fallback: D, f: F) -> R
+where
+    D: FnOnce() -> R,
+    F: FnOnce() -> R,
+{
+    f()
+}
+
+/// Returns the numeric code of the exception, or 0 if `exception` is null.
+#[no_mangle]
+pub extern "C" fn cursed_exception_code(exception: In<Exception>) -> i32 {
+    match unsafe { exception.as_ref() } {
+        Some(exception) => exception.code(),
+        None => 0,
+    }
+}
+
+/// Returns the kind of the exception, or `User` if `exception` is null.
+#[no_mangle]
+pub extern "C" fn cursed_exception_kind(exception: In<Exception>) -> ExceptionKind {
+    match unsafe { exception.as_ref() } {
+        Some(exception) => exception.kind(),
+        None => ExceptionKind::User,
+    }
+}
+
+/// Borrows the message of the exception. The pointer is valid until the exception is freed.
+#[no_mangle]
+pub extern "C" fn cursed_exception_message(exception: In<Exception>) -> *const c_char {
+    match unsafe { exception.as_ref() } {
+        Some(exception) => exception.as_ptr(),
+        None => core::ptr::null(),
+    }
+}
+
+/// Borrows the cause of the exception, if any. The cause is owned by, and freed with, its parent.
+#[no_mangle]
+pub extern "C" fn cursed_exception_cause(exception: In<Exception>) -> Nullable<Exception> {
+    match unsafe { exception.as_ref() }.and_then(Exception::cause) {
+        Some(cause) => Nullable::new(cause),
+        None => null(),
+    }
+}
+
+/// Writes the message as NUL-terminated UTF-16 into `buf`, which holds `len` code units.
+///
+/// The message is truncated if it does not fit. Returns the number of code units required to
+/// hold the whole message including the terminator, so callers may pass a null `buf` first to
+/// size their buffer.
+#[no_mangle]
+pub extern "C" fn cursed_exception_to_utf16(
+    exception: In<Exception>,
+    buf: Out<u16>,
+    len: usize,
+) -> usize {
+    let exception = match unsafe { exception.as_ref() } {
+        Some(exception) => exception,
+        None => return 0,
+    };
+
+    let required = exception.message().encode_utf16().count() + 1;
+
+    if let Some(buf) = buf.as_ptr() {
+        if len > 0 {
+            let buf = unsafe { core::slice::from_raw_parts_mut(buf.as_ptr(), len) };
+            let units = exception
+                .message()
+                .encode_utf16()
+                .chain(core::iter::once(0));
+            let mut written = 0;
+            for (slot, unit) in buf[..len - 1].iter_mut().zip(units) {
+                *slot = unit;
+                written += 1;
+            }
+            buf[written] = 0;
+        }
+    }
+
+    required
+}
+
+/// Frees an exception and its causes.
+#[no_mangle]
+pub extern "C" fn cursed_exception_free(exception: *mut Exception) {
+    if let Some(exception) = NonNull::new(exception) {
+        let exception = unsafe { Exception::from_raw(exception) };
+        log::debug!("EXCEPTION DROPPED: {:?}", exception);
+    }
+}
+
+/// Same as `cursed_exception_code`, kept for existing callers.
+#[no_mangle]
+pub extern "C" fn exception_code(exception: In<Exception>) -> i32 {
+    cursed_exception_code(exception)
+}
+
+/// Same as `cursed_exception_kind`, kept for existing callers.
+#[no_mangle]
+pub extern "C" fn exception_kind(exception: In<Exception>) -> ExceptionKind {
+    cursed_exception_kind(exception)
+}
+
+/// Same as `cursed_exception_message`, kept for existing callers.
+#[no_mangle]
+pub extern "C" fn exception_message(exception: In<Exception>) -> *const c_char {
+    cursed_exception_message(exception)
+}
+
+/// Same as `cursed_exception_cause`, kept for existing callers.
+#[no_mangle]
+pub extern "C" fn exception_cause(exception: In<Exception>) -> Nullable<Exception> {
+    cursed_exception_cause(exception)
+}
+
+/// Same as `cursed_exception_free`, kept for existing callers.
+#[no_mangle]
+pub extern "C" fn exception_free(exception: *mut Exception) {
+    cursed_exception_free(exception)
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "no-std")] {
+            use crate::vendor::c_str::CStr;
+        } else {
+            use std::ffi::CStr;
+        }
+    }
+
+    fn invalid_utf8() -> core::str::Utf8Error {
+        let bytes = vec![b'o', b'h', 0xff];
+        core::str::from_utf8(&bytes).unwrap_err()
+    }
+
+    #[test]
+    fn kinds() {
+        let e = invalid_utf8();
+        assert_eq!(ExceptionKind::of(&e), ExceptionKind::InvalidUtf8);
+        assert_eq!(ExceptionKind::of(&"oh no"), ExceptionKind::User);
+    }
+
+    #[test]
+    fn codes_and_causes() {
+        let cause = Exception::new(ExceptionKind::NullArgument, "inner");
+        let e = Exception::new(ExceptionKind::User, "outer")
+            .with_code(42)
+            .with_cause(cause);
+
+        assert_eq!(e.code(), 42);
+        assert_eq!(e.message(), "outer");
+        assert_eq!(
+            e.cause().unwrap().code(),
+            ExceptionKind::NullArgument as i32
+        );
+        assert_eq!(e.cause().unwrap().message(), "inner");
+    }
+
+    #[test]
+    fn interior_nul() {
+        let e = Exception::new(ExceptionKind::User, "before\0after");
+        assert_eq!(e.message(), "before");
+    }
+
+    #[test]
+    fn round_trip_through_ffi() {
+        let mut raw: *mut Exception = core::ptr::null_mut();
+        let len = crate::vec::ffi::vec_len(In::null(), OutPtr::from(&mut raw));
+        assert_eq!(len, 0);
+        assert!(!raw.is_null());
+
+        let handle = || In::from(unsafe { &*raw });
+        assert_eq!(cursed_exception_kind(handle()), ExceptionKind::NullArgument);
+        assert_eq!(
+            cursed_exception_code(handle()),
+            ExceptionKind::NullArgument as i32
+        );
+
+        let msg = unsafe { CStr::from_ptr(cursed_exception_message(handle())) };
+        assert_eq!(msg.to_str().unwrap(), "handle.as_ptr() must not be null");
+
+        let required = cursed_exception_to_utf16(handle(), Out::null(), 0);
+        let mut wide = vec![0xffffu16; required];
+        let n = cursed_exception_to_utf16(handle(), Out::from(&mut *wide), required);
+        assert_eq!(n, required);
+        assert_eq!(
+            String::from_utf16(&wide[..required - 1]).unwrap(),
+            msg.to_str().unwrap()
+        );
+        assert_eq!(wide[required - 1], 0);
+
+        let mut short = [0xffffu16; 4];
+        cursed_exception_to_utf16(handle(), Out::from(&mut short[..]), 4);
+        assert_eq!(&short, &[b'h' as u16, b'a' as u16, b'n' as u16, 0]);
+
+        cursed_exception_free(raw);
+    }
+
+    #[test]
+    fn unprefixed_exports() {
+        let cause = Exception::new(ExceptionKind::NullArgument, "inner");
+        let raw = Exception::new(ExceptionKind::User, "outer")
+            .with_code(42)
+            .with_cause(cause)
+            .into_raw();
+
+        let handle = || In::from(unsafe { &*raw });
+        assert_eq!(exception_kind(handle()), ExceptionKind::User);
+        assert_eq!(exception_code(handle()), 42);
+        let msg = unsafe { CStr::from_ptr(exception_message(handle())) };
+        assert_eq!(msg.to_str().unwrap(), "outer");
+        let cause = exception_cause(handle()).into_raw();
+        assert_eq!(exception_message(In::from(unsafe { &*cause })), unsafe {
+            (*raw).cause().unwrap().as_ptr()
+        });
+
+        exception_free(raw);
+    }
+
+    #[test]
+    fn into_c_string() {
+        let raw = Exception::new(ExceptionKind::User, "oh no").into_raw();
+        let e = unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) };
+        assert_eq!(e.into_c_string().to_str().unwrap(), "oh no");
+    }
+
+    #[cfg(not(feature = "no-std"))]
+    extern "C" fn panics(divisor: u32, exception: OutPtr<Exception>) -> u32 {
+        ffi_boundary!(&exception, u32::MAX, {
+            if divisor == 0 {
+                panic!("divided by zero");
+            }
+            100 / divisor
+        })
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-std"))]
+    fn panic_becomes_exception() {
+        let mut raw: *mut Exception = core::ptr::null_mut();
+        assert_eq!(panics(4, OutPtr::from(&mut raw)), 25);
+        assert!(raw.is_null());
+
+        assert_eq!(panics(0, OutPtr::from(&mut raw)), u32::MAX);
+        let e = unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::Panic);
+        assert_eq!(e.message(), "panicked at 'divided by zero'");
+    }
+
+    #[test]
+    fn throw_through_out_ptr() {
+        let mut raw: *mut Exception = core::ptr::null_mut();
+        let out = OutPtr::from(&mut raw);
+        let _: Nullable<()> = throw(invalid_utf8(), &out);
+
+        let e = unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::InvalidUtf8);
+    }
+}
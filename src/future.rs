@@ -1,7 +1,404 @@
-use futures::future::*;
-use std::any::Any;
+use alloc::{boxed::Box, format, sync::Arc};
+use core::{
+    ffi::c_void,
+    fmt::Display,
+    future::Future,
+    pin::Pin,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures::task::{waker_ref, ArcWake};
+use parking_lot::Mutex;
+use std::thread::{self, Thread};
 
+use crate::{
+    exception::{catch_panic, throw_null, Exception, ExceptionKind},
+    inout::{In, OutPtr},
+    nullable::{null, Nullable},
+    sync::ArcPtr,
+};
+
+/// Called once when a future completes, with either its result or an exception, both owned by
+/// the callee. The result is released with `arc_drop` and the exception with
+/// `cursed_exception_free`.
+pub type CompletionCallback =
+    extern "C" fn(user_data: *mut c_void, result: ArcPtr<c_void>, exception: Nullable<Exception>);
+
+type Outcome = Result<ArcPtr<c_void>, Exception>;
+
+enum State {
+    Pending(Pin<Box<dyn Future<Output = Outcome> + Send>>),
+    Ready(Outcome),
+    /// The outcome has been given to the completion callback.
+    Done,
+}
+
+struct Completion {
+    callback: CompletionCallback,
+    user_data: *mut c_void,
+}
+
+/// Wakes the thread blocked in `FutureHandle::wait`, if any.
+#[derive(Default)]
+struct Signal {
+    woken: AtomicBool,
+    thread: Mutex<Option<Thread>>,
+}
+
+impl ArcWake for Signal {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::SeqCst);
+        if let Some(thread) = arc_self.thread.lock().as_ref() {
+            thread.unpark();
+        }
+    }
+}
+
+/// A Rust future, driven from C by polling or blocking on it, with its result delivered to a
+/// completion callback.
+pub struct FutureHandle {
+    state: Mutex<State>,
+    completion: Mutex<Option<Completion>>,
+    signal: Arc<Signal>,
+}
+
+impl FutureHandle {
+    pub fn new<F, T, E>(future: F) -> FutureHandle
+    where
+        F: Future<Output = Result<T, E>> + Send + 'static,
+        T: Send + Sync + 'static,
+        E: Display + 'static,
+    {
+        let future = async move {
+            match future.await {
+                Ok(value) => Ok(ArcPtr::new(value).erase()),
+                Err(e) => Err(Exception::new(ExceptionKind::of(&e), format!("{}", e))),
+            }
+        };
+
+        FutureHandle {
+            state: Mutex::new(State::Pending(Box::pin(future))),
+            completion: Mutex::new(None),
+            signal: Arc::new(Signal::default()),
+        }
+    }
+
+    /// Boxes the future to hand to C, which drives it with the `future_` exports and frees it with
+    /// `future_free`.
+    pub fn into_raw(self) -> *mut FutureHandle {
+        let ptr = Box::into_raw(Box::new(self));
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "FutureHandle", core::any::type_name::<FutureHandle>());
+        ptr
+    }
+
+    /// Reclaims a future previously returned by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` and must not have been freed already.
+    pub unsafe fn from_raw(ptr: *mut FutureHandle) -> FutureHandle {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr);
+        *Box::from_raw(ptr)
+    }
+
+    /// Polls the future once, returning whether it has completed.
+    ///
+    /// A panic while polling completes the future with a `Panic` exception.
+    pub fn poll(&self) -> bool {
+        let mut state = self.state.lock();
+        if let State::Pending(future) = &mut *state {
+            let waker = waker_ref(&self.signal);
+            let mut cx = Context::from_waker(&waker);
+
+            let mut raw = core::ptr::null_mut();
+            let poll = catch_panic(
+                &OutPtr::from(&mut raw),
+                || Poll::Pending,
+                || future.as_mut().poll(&mut cx),
+            );
+            match (NonNull::new(raw), poll) {
+                (Some(e), _) => *state = State::Ready(Err(unsafe { Exception::from_raw(e) })),
+                (None, Poll::Ready(outcome)) => *state = State::Ready(outcome),
+                (None, Poll::Pending) => {}
+            }
+        }
+
+        let is_complete = !matches!(*state, State::Pending(_));
+        drop(state);
+
+        self.deliver();
+        is_complete
+    }
+
+    /// Blocks the current thread, polling the future whenever it is woken until it completes.
+    pub fn wait(&self) {
+        *self.signal.thread.lock() = Some(thread::current());
+        while !self.poll() {
+            while !self.signal.woken.swap(false, Ordering::SeqCst) {
+                thread::park();
+            }
+        }
+        *self.signal.thread.lock() = None;
+    }
+
+    /// Drops the future and completes it with a `Cancelled` exception, returning `false` if it
+    /// had already completed.
+    pub fn cancel(&self) -> bool {
+        let mut state = self.state.lock();
+        let is_pending = matches!(*state, State::Pending(_));
+        if is_pending {
+            *state = State::Ready(Err(Exception::new(
+                ExceptionKind::Cancelled,
+                "future was cancelled",
+            )));
+        }
+        drop(state);
+
+        self.deliver();
+        is_pending
+    }
+
+    /// Sets the callback to be called on completion, immediately if already complete.
+    pub fn on_complete(&self, callback: CompletionCallback, user_data: *mut c_void) {
+        *self.completion.lock() = Some(Completion {
+            callback,
+            user_data,
+        });
+        self.deliver();
+    }
+
+    fn deliver(&self) {
+        let mut state = self.state.lock();
+        if !matches!(*state, State::Ready(_)) {
+            return;
+        }
+        let completion = match self.completion.lock().take() {
+            Some(completion) => completion,
+            None => return,
+        };
+        let outcome = match core::mem::replace(&mut *state, State::Done) {
+            State::Ready(outcome) => outcome,
+            _ => unreachable!(),
+        };
+        drop(state);
+
+        // Called without holding either lock, so the callback may poll, cancel or set another
+        // callback.
+        match outcome {
+            Ok(value) => (completion.callback)(completion.user_data, value, null()),
+            Err(e) => (completion.callback)(
+                completion.user_data,
+                ArcPtr::null(),
+                Nullable::from(Box::new(e)),
+            ),
+        }
+    }
+}
+
+impl Drop for FutureHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Sets the callback to be called once when the future completes. If the future has already
+/// completed, the callback is called before this returns.
+#[no_mangle]
+pub extern "C" fn future_on_complete(
+    handle: In<FutureHandle>,
+    callback: CompletionCallback,
+    user_data: *mut c_void,
+    exception: OutPtr<Exception>,
+) {
+    ffi_boundary!(&exception, (), {
+        match unsafe { handle.as_ref() } {
+            Some(handle) => handle.on_complete(callback, user_data),
+            None => {
+                let _: Nullable<()> = throw_null("handle", &exception);
+            }
+        }
+    })
+}
+
+/// Polls the future once without blocking, returning whether it has completed.
+#[no_mangle]
+pub extern "C" fn future_poll(handle: In<FutureHandle>, exception: OutPtr<Exception>) -> bool {
+    ffi_boundary!(&exception, false, {
+        let handle = unsafe { try_as_ref!(handle, &exception, false) };
+        handle.poll()
+    })
+}
+
+/// Blocks the calling thread until the future has completed.
+#[no_mangle]
+pub extern "C" fn future_wait(handle: In<FutureHandle>, exception: OutPtr<Exception>) {
+    ffi_boundary!(&exception, (), {
+        match unsafe { handle.as_ref() } {
+            Some(handle) => handle.wait(),
+            None => {
+                let _: Nullable<()> = throw_null("handle", &exception);
+            }
+        }
+    })
+}
+
+/// Cancels the future, returning `false` if it had already completed.
+#[no_mangle]
+pub extern "C" fn future_cancel(handle: In<FutureHandle>, exception: OutPtr<Exception>) -> bool {
+    ffi_boundary!(&exception, false, {
+        let handle = unsafe { try_as_ref!(handle, &exception, false) };
+        handle.cancel()
+    })
+}
+
+/// Frees a future returned by `FutureHandle::into_raw`, cancelling it if it has not yet completed.
+/// Does nothing if `handle` is null.
 #[no_mangle]
-pub extern "C" fn r#await(f: Box<dyn Future<Output = Box<dyn Any + 'static + Sync + Send>>>) -> ! {
-    unimplemented!()
+pub extern "C" fn future_free(handle: *mut FutureHandle) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        if let Some(handle) = NonNull::new(handle) {
+            drop(unsafe { FutureHandle::from_raw(handle.as_ptr()) });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    type Results = Mutex<Vec<Result<u64, (ExceptionKind, String)>>>;
+
+    extern "C" fn record(
+        user_data: *mut c_void,
+        result: ArcPtr<c_void>,
+        exception: Nullable<Exception>,
+    ) {
+        let results = unsafe { &*(user_data as *const Results) };
+        let outcome = match unsafe { exception.into_box() } {
+            Some(e) => Err((e.kind(), String::from(e.message()))),
+            None => {
+                let value = unsafe { result.cast::<u64>() };
+                Ok(*value)
+            }
+        };
+        results.lock().push(outcome);
+    }
+
+    /// Pending on its first poll, waking itself from another thread.
+    struct Yield(bool);
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            let waker = cx.waker().clone();
+            thread::spawn(move || waker.wake());
+            Poll::Pending
+        }
+    }
+
+    static POLLED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn poll_again(
+        user_data: *mut c_void,
+        _result: ArcPtr<c_void>,
+        _exception: Nullable<Exception>,
+    ) {
+        let handle = unsafe { &*(user_data as *const FutureHandle) };
+        POLLED.store(
+            future_poll(In::from(handle), OutPtr::null()),
+            Ordering::SeqCst,
+        );
+    }
+
+    fn user_data(results: &Results) -> *mut c_void {
+        results as *const Results as *mut c_void
+    }
+
+    #[test]
+    fn wait_and_complete() {
+        let results = Results::default();
+        let handle = FutureHandle::new(async {
+            Yield(false).await;
+            Ok::<_, String>(42u64)
+        });
+
+        future_on_complete(
+            In::from(&handle),
+            record,
+            user_data(&results),
+            OutPtr::null(),
+        );
+        assert!(!future_poll(In::from(&handle), OutPtr::null()));
+        future_wait(In::from(&handle), OutPtr::null());
+
+        assert_eq!(*results.lock(), [Ok(42)]);
+        assert!(!future_cancel(In::from(&handle), OutPtr::null()));
+    }
+
+    #[test]
+    fn errors_and_cancellation() {
+        let results = Results::default();
+
+        let failed = FutureHandle::new(async { Err::<u64, _>("nope") });
+        assert!(failed.poll());
+        failed.on_complete(record, user_data(&results));
+
+        let cancelled = FutureHandle::new(async {
+            Yield(false).await;
+            Ok::<_, String>(1u64)
+        });
+        cancelled.on_complete(record, user_data(&results));
+        assert!(cancelled.cancel());
+
+        let panicked = FutureHandle::new(async {
+            if true {
+                panic!("oh no");
+            }
+            Ok::<_, String>(1u64)
+        });
+        panicked.on_complete(record, user_data(&results));
+        assert!(panicked.poll());
+
+        let results = results.lock();
+        assert_eq!(results[0], Err((ExceptionKind::User, "nope".into())));
+        assert_eq!(results[1].as_ref().unwrap_err().0, ExceptionKind::Cancelled);
+        assert_eq!(results[2].as_ref().unwrap_err().0, ExceptionKind::Panic);
+    }
+
+    #[test]
+    fn free_cancels() {
+        let results = Results::default();
+        let handle = FutureHandle::new(async {
+            Yield(false).await;
+            Ok::<_, String>(1u64)
+        })
+        .into_raw();
+
+        let borrowed = || In::from(unsafe { &*handle });
+        future_on_complete(borrowed(), record, user_data(&results), OutPtr::null());
+        assert!(!future_poll(borrowed(), OutPtr::null()));
+        future_free(handle);
+
+        let results = results.lock();
+        assert_eq!(results[0].as_ref().unwrap_err().0, ExceptionKind::Cancelled);
+    }
+
+    #[test]
+    fn callback_may_poll() {
+        let handle = FutureHandle::new(async { Ok::<_, String>(1u64) });
+        let user_data = &handle as *const FutureHandle as *mut c_void;
+        future_on_complete(In::from(&handle), poll_again, user_data, OutPtr::null());
+        assert!(handle.poll());
+        assert!(POLLED.load(Ordering::SeqCst));
+    }
 }
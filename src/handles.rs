@@ -0,0 +1,322 @@
+//! Generation-checked IDs for values shared with C, in place of raw pointers.
+//!
+//! A `Handle<T>` is passed to C as a `uint64_t` holding a slot in a global table and the slot's
+//! generation, which changes whenever the slot is freed. Looking up a handle that has been freed,
+//! was never issued or refers to another type fails with an `Exception` instead of touching freed
+//! memory, at the cost of a lock per lookup. Use `try_as_handle!` where `try_as_arc!` or
+//! `try_as_ref!` would be used for a pointer.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::fmt;
+use core::marker::PhantomData;
+use parking_lot::{lock_api::RawMutex as _, Mutex, RawMutex};
+
+use crate::exception::Exception;
+use crate::inout::OutPtr;
+
+/// Why a handle could not be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    /// The handle was 0.
+    Null,
+    /// The handle was never issued, or has already been freed.
+    Stale,
+    /// The handle refers to a value of another type.
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::Null => f.write_str("handle is null"),
+            HandleError::Stale => f.write_str("handle is stale or was never issued"),
+            HandleError::WrongType { expected, found } => {
+                write!(f, "handle refers to a `{}`, not a `{}`", found, expected)
+            }
+        }
+    }
+}
+
+type Value = Arc<dyn Any + Send + Sync>;
+
+struct Entry {
+    type_name: &'static str,
+    value: Value,
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+struct Table {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+static TABLE: Mutex<Table> = Mutex::const_new(
+    RawMutex::INIT,
+    Table {
+        slots: Vec::new(),
+        free: Vec::new(),
+    },
+);
+
+fn split(id: u64) -> (usize, u32) {
+    ((id & 0xffff_ffff) as usize, (id >> 32) as u32)
+}
+
+impl Table {
+    fn insert(&mut self, entry: Entry) -> u64 {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                assert!(self.slots.len() < u32::MAX as usize, "too many handles");
+                // Generations start at 1, so that 0 is never a valid handle.
+                self.slots.push(Slot {
+                    generation: 1,
+                    entry: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.entry = Some(entry);
+        (u64::from(slot.generation) << 32) | u64::from(index)
+    }
+
+    fn get(&self, id: u64) -> Result<&Entry, HandleError> {
+        if id == 0 {
+            return Err(HandleError::Null);
+        }
+        let (index, generation) = split(id);
+        match self.slots.get(index) {
+            Some(Slot {
+                generation: g,
+                entry: Some(entry),
+            }) if *g == generation => Ok(entry),
+            _ => Err(HandleError::Stale),
+        }
+    }
+
+    fn remove(&mut self, id: u64) -> Result<Entry, HandleError> {
+        self.get(id)?;
+        let (index, _) = split(id);
+        let slot = &mut self.slots[index];
+        slot.generation = slot.generation.wrapping_add(1).max(1);
+        self.free.push(index as u32);
+        Ok(slot.entry.take().unwrap())
+    }
+}
+
+fn downcast<T: Send + Sync + 'static>(entry: &Entry) -> Result<Arc<T>, HandleError> {
+    Arc::clone(&entry.value)
+        .downcast()
+        .map_err(|_| HandleError::WrongType {
+            expected: core::any::type_name::<T>(),
+            found: entry.type_name,
+        })
+}
+
+/// A generation-checked ID for a shared `T`, for passing to C instead of an `In<T>` or an
+/// `ArcPtr<T>`.
+///
+/// Each issued handle holds one reference to the value until it is freed.
+#[repr(transparent)]
+pub struct Handle<T>(u64, PhantomData<fn() -> T>);
+
+impl<T> Handle<T> {
+    pub fn null() -> Handle<T> {
+        Handle(0, PhantomData)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+
+    pub fn from_id(id: u64) -> Handle<T> {
+        Handle(id, PhantomData)
+    }
+
+    /// Returns whether the handle has been issued and not yet freed.
+    pub fn is_valid(&self) -> bool {
+        TABLE.lock().get(self.0).is_ok()
+    }
+}
+
+impl<T: Send + Sync + 'static> Handle<T> {
+    pub fn new(value: T) -> Handle<T> {
+        Handle::from(Arc::new(value))
+    }
+
+    /// Returns a new reference to the value.
+    pub fn get(&self) -> Result<Arc<T>, HandleError> {
+        downcast(TABLE.lock().get(self.0)?)
+    }
+
+    /// Issues another handle to the same value, which must be freed separately.
+    pub fn share(&self) -> Result<Handle<T>, HandleError> {
+        self.get().map(Handle::from)
+    }
+
+    /// Frees the handle, returning its reference to the value. A handle to another type is not
+    /// freed.
+    pub fn free(self) -> Result<Arc<T>, HandleError> {
+        let mut table = TABLE.lock();
+        let value = downcast(table.get(self.0)?)?;
+        drop(table.remove(self.0));
+        Ok(value)
+    }
+}
+
+impl<T: Send + Sync + 'static> From<Arc<T>> for Handle<T> {
+    fn from(value: Arc<T>) -> Handle<T> {
+        let entry = Entry {
+            type_name: core::any::type_name::<T>(),
+            value,
+        };
+        Handle(TABLE.lock().insert(entry), PhantomData)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (index, generation) = split(self.0);
+        write!(f, "Handle({}v{})", index, generation)
+    }
+}
+
+/// Issues another handle to the value of `handle`, whatever its type, or returns 0 if it is
+/// stale.
+#[no_mangle]
+pub extern "C" fn handle_clone(handle: u64, exception: OutPtr<Exception>) -> u64 {
+    ffi_boundary!(&exception, 0, {
+        let mut table = TABLE.lock();
+        let entry = match table.get(handle) {
+            Ok(entry) => Entry {
+                type_name: entry.type_name,
+                value: Arc::clone(&entry.value),
+            },
+            Err(e) => {
+                drop(table);
+                crate::macros::throw_handle("handle", e, &exception);
+                return 0;
+            }
+        };
+        table.insert(entry)
+    })
+}
+
+/// Frees `handle`, whatever its type, dropping its value if it was the last handle to it.
+#[no_mangle]
+pub extern "C" fn handle_drop(handle: u64, exception: OutPtr<Exception>) {
+    ffi_boundary!(&exception, (), {
+        let entry = TABLE.lock().remove(handle);
+        // Dropped without holding the lock, so the value's destructor may free other handles.
+        match entry {
+            Ok(entry) => drop(entry),
+            Err(e) => crate::macros::throw_handle("handle", e, &exception),
+        }
+    })
+}
+
+/// Returns whether `handle` has been issued and not yet freed.
+#[no_mangle]
+pub extern "C" fn handle_is_valid(handle: u64) -> bool {
+    Handle::<()>::from_id(handle).is_valid()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception::ExceptionKind;
+    use core::ptr::NonNull;
+
+    fn kind(raw: *mut Exception) -> ExceptionKind {
+        unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) }.kind()
+    }
+
+    #[test]
+    fn lifecycle() {
+        let handle = Handle::new(42u64);
+        assert_eq!(*handle.get().unwrap(), 42);
+        let shared = handle.share().unwrap();
+        assert_ne!(shared, handle);
+
+        let value = handle.free().unwrap();
+        assert_eq!(Arc::strong_count(&value), 2);
+        assert_eq!(handle.get(), Err(HandleError::Stale));
+        assert_eq!(handle.free(), Err(HandleError::Stale));
+        assert_eq!(*shared.get().unwrap(), 42);
+
+        let wrong = Handle::<u32>::from_id(shared.id());
+        assert!(matches!(wrong.free(), Err(HandleError::WrongType { .. })));
+        assert!(shared.free().is_ok());
+        assert_eq!(Handle::<u64>::null().get(), Err(HandleError::Null));
+    }
+
+    #[test]
+    fn reused_slots_are_not_confused() {
+        let old = Handle::new(1u8);
+        old.free().unwrap();
+        let new = Handle::new(2u8);
+        assert_eq!(old.get(), Err(HandleError::Stale));
+        assert_eq!(*new.get().unwrap(), 2);
+        new.free().unwrap();
+    }
+
+    fn read(handle: Handle<u64>, exception: OutPtr<Exception>) -> u64 {
+        let value = try_as_handle!(handle, &exception, 0);
+        *value
+    }
+
+    #[test]
+    fn ffi() {
+        let handle = Handle::new(7u64);
+        let cloned = handle_clone(handle.id(), OutPtr::null());
+        assert_eq!(read(Handle::from_id(cloned), OutPtr::null()), 7);
+
+        handle_drop(handle.id(), OutPtr::null());
+        assert!(!handle_is_valid(handle.id()));
+        assert!(handle_is_valid(cloned));
+
+        let mut raw = core::ptr::null_mut();
+        handle_drop(handle.id(), OutPtr::from(&mut raw));
+        assert_eq!(kind(raw), ExceptionKind::InvalidHandle);
+        assert_eq!(read(handle, OutPtr::from(&mut raw)), 0);
+        assert_eq!(kind(raw), ExceptionKind::InvalidHandle);
+        assert_eq!(read(Handle::null(), OutPtr::from(&mut raw)), 0);
+        assert_eq!(kind(raw), ExceptionKind::NullArgument);
+
+        let wrong = Handle::new(1u8);
+        assert_eq!(read(Handle::from_id(wrong.id()), OutPtr::from(&mut raw)), 0);
+        assert_eq!(kind(raw), ExceptionKind::TypeMismatch);
+        handle_drop(wrong.id(), OutPtr::null());
+        handle_drop(cloned, OutPtr::null());
+    }
+}
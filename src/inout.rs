@@ -15,6 +15,25 @@ impl<T: ?Sized> From<Box<T>> for In<T> {
     }
 }
 
+impl<T: ?Sized> From<&T> for In<T> {
+    fn from(value: &T) -> In<T> {
+        In(value)
+    }
+}
+
+impl<T> In<T> {
+    #[inline]
+    pub fn null() -> In<T> {
+        In(core::ptr::null())
+    }
+
+    /// Reinterprets the pointer as pointing to a `U`.
+    #[inline]
+    pub fn cast<U>(self) -> In<U> {
+        In(self.0 as *const U)
+    }
+}
+
 impl<T: ?Sized> In<T> {
     #[inline]
     pub fn is_null(&self) -> bool {
@@ -26,6 +45,12 @@ impl<T: ?Sized> In<T> {
         NonNull::new(self.0 as *mut _)
     }
 
+    /// Borrows the value, if the pointer is not null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must be aligned and point to a valid `T` that is not changed while
+    /// borrowed.
     #[inline]
     pub unsafe fn as_ref(&self) -> Option<&T> {
         match self.is_null() {
@@ -35,27 +60,19 @@ impl<T: ?Sized> In<T> {
     }
 }
 
-#[doc(hidden)]
-fn as_arc_internal<T, U>(arc: &In<U>) -> Option<Arc<T>> {
-    if let Some(arc) = arc.as_ptr() {
-        let arc = unsafe { Arc::from_raw(arc.as_ptr() as *const _) };
-        let arc1: Arc<T> = Arc::clone(&arc);
-        core::mem::forget(arc);
-        Some(arc1)
-    } else {
-        None
-    }
-}
-
 impl<T> In<Arc<T>> {
+    /// Returns a new reference to the `Arc` pointed to, such as one boxed by `vec_pop`.
     pub fn as_arc(&self) -> Option<Arc<T>> {
-        as_arc_internal(self)
+        unsafe { self.as_ref() }.cloned()
     }
 }
 
 impl<T> In<crate::sync::ArcPtr<T>> {
-    pub fn as_arc(&self) -> Option<Arc<T>> {
-        as_arc_internal(self)
+    /// Returns a new reference to the value, as the pointer is the `ArcPtr` itself.
+    pub fn as_arc(&self) -> Option<crate::sync::ArcPtr<T>> {
+        let ptr = self.as_ptr()?.as_ptr() as *const T;
+        let arc = core::mem::ManuallyDrop::new(unsafe { crate::sync::ArcPtr::from_raw(ptr) });
+        Some((*arc).clone())
     }
 }
 
@@ -65,6 +82,25 @@ pub struct Out<T: ?Sized>(*mut T);
 unsafe impl<T: ?Sized> Sync for Out<T> {}
 unsafe impl<T: ?Sized> Send for Out<T> {}
 
+impl<T: ?Sized> From<&mut T> for Out<T> {
+    fn from(value: &mut T) -> Out<T> {
+        Out(value)
+    }
+}
+
+impl<T> From<&mut [T]> for Out<T> {
+    fn from(slice: &mut [T]) -> Out<T> {
+        Out(slice.as_mut_ptr())
+    }
+}
+
+impl<T> Out<T> {
+    #[inline]
+    pub fn null() -> Out<T> {
+        Out(core::ptr::null_mut())
+    }
+}
+
 impl<T: ?Sized> Out<T> {
     #[inline]
     pub fn is_null(&self) -> bool {
@@ -76,6 +112,12 @@ impl<T: ?Sized> Out<T> {
         NonNull::new(self.0)
     }
 
+    /// Mutably borrows the value, if the pointer is not null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must be aligned and point to a valid `T` that is not otherwise
+    /// accessed while borrowed.
     #[inline]
     pub unsafe fn as_mut_ref(&mut self) -> Option<&mut T> {
         match self.is_null() {
@@ -91,6 +133,19 @@ pub struct OutPtr<T: ?Sized>(*mut *mut T);
 unsafe impl<T: ?Sized> Sync for OutPtr<T> {}
 unsafe impl<T: ?Sized> Send for OutPtr<T> {}
 
+impl<T: ?Sized> From<&mut *mut T> for OutPtr<T> {
+    fn from(ptr: &mut *mut T) -> OutPtr<T> {
+        OutPtr(ptr)
+    }
+}
+
+impl<T> OutPtr<T> {
+    #[inline]
+    pub fn null() -> OutPtr<T> {
+        OutPtr(core::ptr::null_mut())
+    }
+}
+
 impl<T: ?Sized> OutPtr<T> {
     #[inline]
     pub fn is_null(&self) -> bool {
@@ -102,6 +157,12 @@ impl<T: ?Sized> OutPtr<T> {
         NonNull::new(self.0)
     }
 
+    /// Borrows the location to write the pointer to, if it is not null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must be aligned and point to a `*mut T` that is not otherwise accessed
+    /// while borrowed.
     #[inline]
     pub unsafe fn as_mut_ref(&mut self) -> Option<&mut *mut T> {
         match self.is_null() {
@@ -117,6 +178,19 @@ pub struct InOut<T: ?Sized>(*mut T);
 unsafe impl<T: ?Sized> Sync for InOut<T> {}
 unsafe impl<T: ?Sized> Send for InOut<T> {}
 
+impl<T: ?Sized> From<&mut T> for InOut<T> {
+    fn from(value: &mut T) -> InOut<T> {
+        InOut(value)
+    }
+}
+
+impl<T> InOut<T> {
+    #[inline]
+    pub fn null() -> InOut<T> {
+        InOut(core::ptr::null_mut())
+    }
+}
+
 impl<T: ?Sized> InOut<T> {
     #[inline]
     pub fn is_null(&self) -> bool {
@@ -128,6 +202,12 @@ impl<T: ?Sized> InOut<T> {
         NonNull::new(self.0)
     }
 
+    /// Borrows the value, if the pointer is not null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must be aligned and point to a valid `T` that is not changed while
+    /// borrowed.
     #[inline]
     pub unsafe fn as_ref(&self) -> Option<&T> {
         match self.is_null() {
@@ -136,6 +216,12 @@ impl<T: ?Sized> InOut<T> {
         }
     }
 
+    /// Mutably borrows the value, if the pointer is not null.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must be aligned and point to a valid `T` that is not otherwise
+    /// accessed while borrowed.
     #[inline]
     pub unsafe fn as_mut_ref(&mut self) -> Option<&mut T> {
         match self.is_null() {
@@ -158,3 +244,181 @@ impl<T: ?Sized> InOut<T> {
 pub type InRaw = In<c_void>;
 pub type OutRaw = Out<c_void>;
 pub type InOutRaw = InOut<c_void>;
+
+/// Why a slice argument could not be borrowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceError {
+    /// The pointer was null, but the length was not zero.
+    Null,
+    /// The pointer was not aligned for `T`.
+    Misaligned,
+    /// The length in bytes does not fit in an `isize`.
+    TooLong,
+}
+
+impl core::fmt::Display for SliceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(match self {
+            SliceError::Null => "pointer is null but length is not zero",
+            SliceError::Misaligned => "pointer is misaligned",
+            SliceError::TooLong => "length is too long",
+        })
+    }
+}
+
+/// Checks that `ptr` and `len` describe a slice that may be borrowed. A null pointer with a zero
+/// length is an empty slice.
+fn check_slice<T>(ptr: *const T, len: usize) -> Result<*const T, SliceError> {
+    if ptr.is_null() {
+        return match len {
+            0 => Ok(NonNull::dangling().as_ptr()),
+            _ => Err(SliceError::Null),
+        };
+    }
+    if !ptr.is_aligned() {
+        return Err(SliceError::Misaligned);
+    }
+    match len.checked_mul(core::mem::size_of::<T>()) {
+        Some(size) if size <= isize::MAX as usize => Ok(ptr),
+        _ => Err(SliceError::TooLong),
+    }
+}
+
+/// A borrowed array passed as a pointer and a length.
+#[repr(C)]
+#[derive(Debug)]
+pub struct InSlice<T> {
+    ptr: *const T,
+    len: usize,
+}
+unsafe impl<T> Sync for InSlice<T> {}
+unsafe impl<T> Send for InSlice<T> {}
+
+impl<T> From<&[T]> for InSlice<T> {
+    fn from(slice: &[T]) -> InSlice<T> {
+        InSlice {
+            ptr: slice.as_ptr(),
+            len: slice.len(),
+        }
+    }
+}
+
+impl<T> InSlice<T> {
+    /// Wraps a pointer and length as passed from C, without checking them.
+    #[inline]
+    pub fn from_raw_parts(ptr: *const T, len: usize) -> InSlice<T> {
+        InSlice { ptr, len }
+    }
+
+    #[inline]
+    pub fn null() -> InSlice<T> {
+        InSlice {
+            ptr: core::ptr::null(),
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Borrows the slice, failing if the pointer is null with a non-zero length or misaligned.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must point to `len` valid `T`s that are not changed while borrowed.
+    pub unsafe fn as_slice(&self) -> Result<&[T], SliceError> {
+        let ptr = check_slice(self.ptr, self.len)?;
+        Ok(core::slice::from_raw_parts(ptr, self.len))
+    }
+}
+
+/// A borrowed, mutable array passed as a pointer and a length.
+#[repr(C)]
+#[derive(Debug)]
+pub struct OutSlice<T> {
+    ptr: *mut T,
+    len: usize,
+}
+unsafe impl<T> Sync for OutSlice<T> {}
+unsafe impl<T> Send for OutSlice<T> {}
+
+impl<T> From<&mut [T]> for OutSlice<T> {
+    fn from(slice: &mut [T]) -> OutSlice<T> {
+        OutSlice {
+            ptr: slice.as_mut_ptr(),
+            len: slice.len(),
+        }
+    }
+}
+
+impl<T> OutSlice<T> {
+    /// Wraps a pointer and length as passed from C, without checking them.
+    #[inline]
+    pub fn from_raw_parts(ptr: *mut T, len: usize) -> OutSlice<T> {
+        OutSlice { ptr, len }
+    }
+
+    #[inline]
+    pub fn null() -> OutSlice<T> {
+        OutSlice {
+            ptr: core::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Borrows the slice, failing if the pointer is null with a non-zero length or misaligned.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must point to `len` valid `T`s that are not changed while borrowed.
+    pub unsafe fn as_slice(&self) -> Result<&[T], SliceError> {
+        let ptr = check_slice(self.ptr, self.len)?;
+        Ok(core::slice::from_raw_parts(ptr, self.len))
+    }
+
+    /// Mutably borrows the slice, failing if the pointer is null with a non-zero length or
+    /// misaligned.
+    ///
+    /// # Safety
+    ///
+    /// A non-null pointer must point to `len` valid `T`s that are not otherwise accessed while
+    /// borrowed.
+    pub unsafe fn as_mut_slice(&mut self) -> Result<&mut [T], SliceError> {
+        let ptr = check_slice(self.ptr, self.len)? as *mut T;
+        Ok(core::slice::from_raw_parts_mut(ptr, self.len))
+    }
+
+    pub fn to_in(&self) -> InSlice<T> {
+        InSlice {
+            ptr: self.ptr,
+            len: self.len,
+        }
+    }
+}
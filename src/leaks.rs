@@ -0,0 +1,238 @@
+//! Records objects handed across the FFI until they are released, to find leaks.
+//!
+//! With the `leak-tracker` feature, every `ArcPtr` allocation, `Vec::into_raw`,
+//! `Exception::into_raw` and `CCharPtr` is recorded with its type name, and a backtrace when
+//! `RUST_BACKTRACE` is set. `cursed_leak_report` describes the objects still live as JSON, and a
+//! `LeakCheck` guard fails a test if any object it created outlives it.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+use parking_lot::{lock_api::RawMutex as _, Mutex, RawMutex};
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::thread::{self, ThreadId};
+
+use crate::c_char::{CCharPtr, StringExt};
+
+/// An object that has been created and not yet released.
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub kind: &'static str,
+    pub type_name: &'static str,
+    pub address: usize,
+    thread: ThreadId,
+    sequence: u64,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl Allocation {
+    /// Where the object was created, if backtraces are enabled.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+static LIVE: Mutex<BTreeMap<usize, Allocation>> = Mutex::const_new(RawMutex::INIT, BTreeMap::new());
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn track<T: ?Sized>(address: *const T, kind: &'static str, type_name: &'static str) {
+    let backtrace = Backtrace::capture();
+    let allocation = Allocation {
+        kind,
+        type_name,
+        address: address as *const u8 as usize,
+        thread: thread::current().id(),
+        sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        backtrace: match backtrace.status() {
+            BacktraceStatus::Captured => Some(Arc::new(backtrace)),
+            _ => None,
+        },
+    };
+    LIVE.lock().insert(allocation.address, allocation);
+}
+
+pub(crate) fn untrack<T: ?Sized>(address: *const T) {
+    LIVE.lock().remove(&(address as *const u8 as usize));
+}
+
+/// Returns the objects that are live, oldest first.
+pub fn live() -> Vec<Allocation> {
+    let mut live: Vec<_> = LIVE.lock().values().cloned().collect();
+    live.sort_by_key(|x| x.sequence);
+    live
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Describes the given objects as a JSON array.
+pub fn report(allocations: &[Allocation]) -> String {
+    let entries: Vec<String> = allocations
+        .iter()
+        .map(|x| {
+            let backtrace = match x.backtrace() {
+                Some(backtrace) => format!("\"{}\"", escape(&format!("{}", backtrace))),
+                None => "null".into(),
+            };
+            format!(
+                "{{\"kind\":\"{}\",\"type\":\"{}\",\"address\":\"{:#x}\",\"backtrace\":{}}}",
+                escape(x.kind),
+                escape(x.type_name),
+                x.address,
+                backtrace
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+/// Fails the current test, by panicking when dropped, if any object created on this thread since
+/// it was created is still live.
+pub struct LeakCheck {
+    thread: ThreadId,
+    since: u64,
+}
+
+impl LeakCheck {
+    pub fn new() -> LeakCheck {
+        LeakCheck {
+            thread: thread::current().id(),
+            since: SEQUENCE.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the objects created on this thread since the check began that are still live.
+    pub fn leaks(&self) -> Vec<Allocation> {
+        live()
+            .into_iter()
+            .filter(|x| x.thread == self.thread && x.sequence >= self.since)
+            .collect()
+    }
+}
+
+impl Default for LeakCheck {
+    fn default() -> LeakCheck {
+        LeakCheck::new()
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        let leaks = self.leaks();
+        if !leaks.is_empty() && !thread::panicking() {
+            panic!("{} objects leaked: {}", leaks.len(), report(&leaks));
+        }
+    }
+}
+
+/// Returns a JSON array describing every live object, released with `cursed_string_free`.
+#[no_mangle]
+pub extern "C" fn cursed_leak_report() -> CCharPtr {
+    // Type names and escaped backtraces never contain a NUL.
+    report(&live()).into_c_char().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception::{Exception, ExceptionKind};
+    use crate::sync::ArcPtr;
+    use core::ptr::NonNull;
+
+    #[test]
+    fn tracks_until_released() {
+        let check = LeakCheck::new();
+
+        let arc = ArcPtr::new(42u64);
+        let exception = Exception::new(ExceptionKind::User, "oh no").into_raw();
+        let vec = crate::vec::Vec::<u64>::new().into_raw();
+        let string = String::from("leaky").into_c_char().unwrap().into_raw();
+
+        let leaks = check.leaks();
+        let kinds: Vec<_> = leaks.iter().map(|x| x.kind).collect();
+        assert_eq!(kinds, ["ArcPtr", "Exception", "Vec", "CCharPtr"]);
+        assert_eq!(leaks[0].type_name, "u64");
+        assert_eq!(leaks[0].address, arc.as_ptr() as usize);
+        assert!(report(&leaks)
+            .contains("\"kind\":\"Exception\",\"type\":\"cursed::exception::Exception\""));
+
+        drop(arc);
+        drop(unsafe { Exception::from_raw(NonNull::new(exception).unwrap()) });
+        drop(unsafe { crate::vec::Vec::<u64>::from_raw(vec) });
+        crate::c_char::cursed_string_free(string);
+        assert!(check.leaks().is_empty());
+    }
+
+    #[test]
+    fn tracks_vector_exports() {
+        use crate::inout::{In, OutPtr};
+        use crate::nullable::Nullable;
+        use crate::vec::{ffi, frozen, RawVec, Vec as CursedVec};
+
+        let check = LeakCheck::new();
+        let vec = CursedVec::<u64>::new().into_raw();
+        let handle = || In::from(unsafe { &*(vec as *const RawVec) });
+        let shared = ffi::vec_share(handle(), OutPtr::null()).into_raw();
+        let cloned = ffi::vec_deep_clone(handle(), OutPtr::null()).into_raw();
+        let frozen = frozen::vec_snapshot(handle(), OutPtr::null()).into_raw();
+
+        let leaks = check.leaks();
+        let kinds: Vec<_> = leaks.iter().map(|x| x.kind).collect();
+        assert_eq!(kinds, ["Vec", "Vec", "Vec", "FrozenVec"]);
+        assert!(leaks.iter().all(|x| x.type_name == "u64"));
+
+        for raw in [vec, shared as *const _, cloned as *const _] {
+            drop(unsafe { CursedVec::<u64>::from_raw(raw) });
+        }
+        frozen::frozen_vec_free(unsafe { Nullable::from_raw(frozen) });
+        assert!(check.leaks().is_empty());
+    }
+
+    #[test]
+    fn tracks_primitive_vectors() {
+        use crate::inout::{In, OutPtr};
+        use crate::vec::primitive::{primitive_vec_free, vec_from_buffer, PRIMITIVE_U32};
+
+        let check = LeakCheck::new();
+        let input = [1u32, 2, 3];
+        let input = In::from(&input[0]).cast();
+        let vec = vec_from_buffer(PRIMITIVE_U32, input, 3, OutPtr::null()).into_raw();
+
+        let leaks = check.leaks();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!((leaks[0].kind, leaks[0].type_name), ("PrimitiveVec", "u32"));
+        assert_eq!(leaks[0].address, vec as usize);
+
+        primitive_vec_free(vec as *mut _);
+        assert!(check.leaks().is_empty());
+    }
+
+    #[test]
+    fn fails_on_leaks() {
+        let leaked = std::panic::catch_unwind(|| {
+            let _check = LeakCheck::new();
+            core::mem::forget(ArcPtr::new(String::from("leaked")));
+        });
+        assert!(leaked.is_err());
+
+        let report = cursed_leak_report();
+        assert!(report.as_str().unwrap().contains("alloc::string::String"));
+    }
+}
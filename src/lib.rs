@@ -1,4 +1,3 @@
-#![feature(const_type_id)]
 #![feature(proc_macro_hygiene)]
 #![no_std]
 
@@ -6,27 +5,173 @@ extern crate alloc;
 #[cfg(not(feature = "no-std"))]
 extern crate std;
 
+// Allows `#[export]` to refer to `::cursed` from within this crate, too.
+extern crate self as cursed;
+
 #[macro_use]
 pub mod macros;
+pub mod bytes;
+pub mod callback;
 pub mod exception;
 #[cfg(feature = "futures")]
 pub mod future;
+#[cfg(feature = "handles")]
+pub mod handles;
 pub mod inout;
+#[cfg(feature = "leak-tracker")]
+pub mod leaks;
+pub mod lock;
+pub mod map;
 pub mod nullable;
 pub mod sync;
+pub mod tag;
 pub mod vec;
+pub mod wide;
 pub mod c_char;
 mod vendor;
 
+#[cfg(all(feature = "leak-tracker", feature = "no-std"))]
+compile_error!("the `leak-tracker` feature requires std");
+
+pub use cursed_macros::export;
+
+#[doc(hidden)]
+pub use libc;
+#[doc(hidden)]
+pub use log;
+
 pub mod prelude {
+    pub use crate::bytes::*;
+    pub use crate::callback::*;
     pub use crate::exception::*;
     #[cfg(feature = "futures")]
     pub use crate::future::*;
+    #[cfg(feature = "handles")]
+    pub use crate::handles::*;
     pub use crate::inout::*;
+    pub use crate::lock::*;
+    pub use crate::map::{Map, MapIter, RawMap, RawMapIter};
     pub use crate::macros::*;
     pub use crate::nullable::*;
     pub use crate::sync::*;
+    pub use crate::tag::*;
     pub use crate::vec::*;
+    pub use crate::wide::*;
     pub use crate::c_char::*;
-    pub use crate::{try_as_arc, try_as_ref, try_as_str, try_into_arc, try_not_null};
+    pub use crate::export;
+    pub use crate::{
+        ffi_boundary, try_as_arc, try_as_mut_slice, try_as_ref, try_as_slice, try_as_str,
+        try_as_wide_str, try_into_arc, try_not_null,
+    };
+    #[cfg(feature = "handles")]
+    pub use crate::try_as_handle;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use alloc::sync::Arc;
+    use core::ptr::NonNull;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Counter(u64);
+
+    #[export]
+    fn counter_add(counter: &mut Counter, amount: u64) -> u64 {
+        counter.0 += amount;
+        counter.0
+    }
+
+    #[export]
+    fn counter_parse(input: &str) -> Result<ArcPtr<Counter>, core::num::ParseIntError> {
+        Ok(ArcPtr::new(Counter(input.parse()?)))
+    }
+
+    #[export]
+    fn counter_double(counter: Arc<Counter>) -> Arc<Counter> {
+        Arc::new(Counter(counter.0 * 2))
+    }
+
+    #[export]
+    fn counter_sum(counters: Vec<Counter>) -> u64 {
+        counters.to_vec().unwrap().iter().map(|x| x.0).sum()
+    }
+
+    #[export]
+    fn counter_scale(counters: &mut [Counter], factors: &[u64]) -> usize {
+        for (counter, factor) in counters.iter_mut().zip(factors) {
+            counter.0 *= factor;
+        }
+        counters.len().min(factors.len())
+    }
+
+    fn take_exception(raw: *mut Exception) -> Exception {
+        unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) }
+    }
+
+    #[test]
+    fn export_refs() {
+        let mut raw = core::ptr::null_mut();
+        let mut counter = Counter(1);
+        let result = counter_add(InOut::from(&mut counter), 2, OutPtr::from(&mut raw));
+        assert_eq!(result, 3);
+        assert_eq!(counter, Counter(3));
+        assert!(raw.is_null());
+
+        assert_eq!(counter_add(InOut::null(), 2, OutPtr::from(&mut raw)), 0);
+        let e = take_exception(raw);
+        assert_eq!(e.kind(), ExceptionKind::NullArgument);
+        assert_eq!(e.message(), "counter must not be null");
+    }
+
+    #[test]
+    fn export_results() {
+        let mut raw = core::ptr::null_mut();
+        let input = b"42\0";
+        let input = unsafe { &*(input.as_ptr() as *const libc::c_char) };
+        let result = counter_parse(In::from(input), OutPtr::from(&mut raw));
+        assert!(raw.is_null());
+        assert_eq!(result.0, 42);
+
+        let input = b"nope\0";
+        let input = unsafe { &*(input.as_ptr() as *const libc::c_char) };
+        let result = counter_parse(In::from(input), OutPtr::from(&mut raw));
+        assert!(result.is_null());
+        assert_eq!(take_exception(raw).kind(), ExceptionKind::User);
+    }
+
+    #[test]
+    fn export_arcs() {
+        let counter = ArcPtr::new(Counter(21));
+        let input = In::from(&*counter).cast::<ArcPtr<Counter>>();
+        let doubled = counter_double(input, OutPtr::null());
+        assert_eq!(*doubled, Counter(42));
+        assert_eq!(counter.strong_count(), 1);
+        assert_eq!(doubled.strong_count(), 1);
+    }
+
+    #[test]
+    fn export_slices() {
+        let mut counters = [Counter(1), Counter(2)];
+        let factors = [3, 4, 5];
+        let scaled = counter_scale(
+            OutSlice::from(&mut counters[..]),
+            InSlice::from(&factors[..]),
+            OutPtr::null(),
+        );
+        assert_eq!(scaled, 2);
+        assert_eq!(counters, [Counter(3), Counter(8)]);
+
+        let mut raw = core::ptr::null_mut();
+        let factors = InSlice::from_raw_parts(core::ptr::null(), 1);
+        assert_eq!(counter_scale(OutSlice::null(), factors, OutPtr::from(&mut raw)), 0);
+        assert_eq!(take_exception(raw).kind(), ExceptionKind::NullArgument);
+    }
+
+    #[test]
+    fn export_vecs() {
+        let mut raw = core::ptr::null_mut();
+        let vec = Vec::from(alloc::vec![Counter(1), Counter(2)]);
+        assert_eq!(counter_sum(In::from(&vec), OutPtr::from(&mut raw)), 3);
+    }
 }
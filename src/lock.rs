@@ -0,0 +1,743 @@
+//! Locks that foreign code can hold, for coordinating on state shared through an `ArcPtr`.
+//!
+//! `Mutex<T>` and `RwLock<T>` are shared with C as an `ArcPtr<Mutex<T>>` or `ArcPtr<RwLock<T>>`.
+//! Locking one returns a `LockGuard`, which C unlocks with `lock_guard_unlock` and frees with
+//! `lock_guard_free`. As with `std::sync`, a lock is poisoned if a Rust guard to it is dropped
+//! while panicking, and locking a poisoned lock fails until `lock_clear_poison` is called.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::ffi::c_void;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use parking_lot::lock_api::{
+    RawMutex as _, RawMutexTimed as _, RawRwLock as _, RawRwLockTimed as _,
+};
+use parking_lot::{RawMutex, RawRwLock};
+
+use crate::exception::{throw_kind, Exception, ExceptionKind};
+use crate::inout::{InOut, OutPtr};
+use crate::nullable::{null, Nullable};
+use crate::sync::ArcPtr;
+
+/// Why a lock could not be taken or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockError {
+    /// A guard was dropped while panicking, so the value may be inconsistent.
+    Poisoned,
+    /// The lock is held elsewhere.
+    WouldBlock,
+    /// The lock was not taken before the timeout.
+    TimedOut,
+    /// The guard has already been unlocked.
+    NotHeld,
+    /// The pointer is to another kind of lock.
+    WrongKind,
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LockError::Poisoned => "lock is poisoned",
+            LockError::WouldBlock => "lock is held elsewhere",
+            LockError::TimedOut => "timed out waiting for lock",
+            LockError::NotHeld => "guard is not held",
+            LockError::WrongKind => "lock is of another kind",
+        })
+    }
+}
+
+#[cfg(not(feature = "no-std"))]
+fn panicking() -> bool {
+    std::thread::panicking()
+}
+
+#[cfg(feature = "no-std")]
+fn panicking() -> bool {
+    false
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Mutex,
+    RwLock,
+}
+
+/// Begins every `Mutex` and `RwLock`, so the FFI can check what it has been given.
+#[repr(C)]
+struct Header {
+    kind: Kind,
+    poisoned: AtomicBool,
+    /// The offset of the value from the header, so C can be given a pointer to it.
+    data_offset: usize,
+}
+
+impl Header {
+    fn check(&self) -> Result<(), LockError> {
+        match self.poisoned.load(Ordering::Acquire) {
+            true => Err(LockError::Poisoned),
+            false => Ok(()),
+        }
+    }
+
+    fn poison_if_panicking(&self) {
+        if panicking() {
+            self.poisoned.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// A mutual exclusion lock that can be held by foreign code.
+#[repr(C)]
+pub struct Mutex<T> {
+    header: Header,
+    raw: RawMutex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Mutex<T> {
+        Mutex {
+            header: Header {
+                kind: Kind::Mutex,
+                poisoned: AtomicBool::new(false),
+                data_offset: core::mem::offset_of!(Mutex<T>, data),
+            },
+            raw: RawMutex::INIT,
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    fn guard(&self) -> Result<MutexGuard<'_, T>, LockError> {
+        let guard = MutexGuard(self);
+        self.header.check().map(|_| guard)
+    }
+
+    /// Blocks until the lock is taken. Fails, releasing the lock, if it is poisoned.
+    pub fn lock(&self) -> Result<MutexGuard<'_, T>, LockError> {
+        self.raw.lock();
+        self.guard()
+    }
+
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, LockError> {
+        match self.raw.try_lock() {
+            true => self.guard(),
+            false => Err(LockError::WouldBlock),
+        }
+    }
+
+    pub fn try_lock_for(&self, timeout: Duration) -> Result<MutexGuard<'_, T>, LockError> {
+        match self.raw.try_lock_for(timeout) {
+            true => self.guard(),
+            false => Err(LockError::TimedOut),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.header.poisoned.load(Ordering::Acquire)
+    }
+
+    pub fn clear_poison(&self) {
+        self.header.poisoned.store(false, Ordering::Release);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct MutexGuard<'a, T>(&'a Mutex<T>);
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.header.poison_if_panicking();
+        self.0.raw.unlock();
+    }
+}
+
+/// A reader-writer lock that can be held by foreign code.
+#[repr(C)]
+pub struct RwLock<T> {
+    header: Header,
+    raw: RawRwLock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub fn new(value: T) -> RwLock<T> {
+        RwLock {
+            header: Header {
+                kind: Kind::RwLock,
+                poisoned: AtomicBool::new(false),
+                data_offset: core::mem::offset_of!(RwLock<T>, data),
+            },
+            raw: RawRwLock::INIT,
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    fn read_guard(&self) -> Result<RwLockReadGuard<'_, T>, LockError> {
+        let guard = RwLockReadGuard(self);
+        self.header.check().map(|_| guard)
+    }
+
+    fn write_guard(&self) -> Result<RwLockWriteGuard<'_, T>, LockError> {
+        let guard = RwLockWriteGuard(self);
+        self.header.check().map(|_| guard)
+    }
+
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, T>, LockError> {
+        self.raw.lock_shared();
+        self.read_guard()
+    }
+
+    pub fn try_read(&self) -> Result<RwLockReadGuard<'_, T>, LockError> {
+        match self.raw.try_lock_shared() {
+            true => self.read_guard(),
+            false => Err(LockError::WouldBlock),
+        }
+    }
+
+    pub fn try_read_for(&self, timeout: Duration) -> Result<RwLockReadGuard<'_, T>, LockError> {
+        match self.raw.try_lock_shared_for(timeout) {
+            true => self.read_guard(),
+            false => Err(LockError::TimedOut),
+        }
+    }
+
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, T>, LockError> {
+        self.raw.lock_exclusive();
+        self.write_guard()
+    }
+
+    pub fn try_write(&self) -> Result<RwLockWriteGuard<'_, T>, LockError> {
+        match self.raw.try_lock_exclusive() {
+            true => self.write_guard(),
+            false => Err(LockError::WouldBlock),
+        }
+    }
+
+    pub fn try_write_for(&self, timeout: Duration) -> Result<RwLockWriteGuard<'_, T>, LockError> {
+        match self.raw.try_lock_exclusive_for(timeout) {
+            true => self.write_guard(),
+            false => Err(LockError::TimedOut),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.header.poisoned.load(Ordering::Acquire)
+    }
+
+    pub fn clear_poison(&self) {
+        self.header.poisoned.store(false, Ordering::Release);
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T>(&'a RwLock<T>);
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.raw.unlock_shared();
+    }
+}
+
+/// Poisons the lock if dropped while panicking, as only a writer can leave the value
+/// inconsistent.
+pub struct RwLockWriteGuard<'a, T>(&'a RwLock<T>);
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.data.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.header.poison_if_panicking();
+        self.0.raw.unlock_exclusive();
+    }
+}
+
+/// The parts of a `Mutex` or `RwLock` that do not depend on the type of its value.
+#[repr(C)]
+struct Raw<R> {
+    header: Header,
+    raw: R,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Lock,
+    Read,
+    Write,
+}
+
+/// A lock taken by foreign code, which keeps the lock alive until it is freed.
+pub struct LockGuard {
+    lock: ArcPtr<c_void>,
+    mode: Mode,
+    held: bool,
+}
+
+impl LockGuard {
+    fn header(&self) -> &Header {
+        unsafe { &*(self.lock.as_ptr() as *const Header) }
+    }
+
+    fn mutex(&self) -> &RawMutex {
+        unsafe { &(*(self.lock.as_ptr() as *const Raw<RawMutex>)).raw }
+    }
+
+    fn rwlock(&self) -> &RawRwLock {
+        unsafe { &(*(self.lock.as_ptr() as *const Raw<RawRwLock>)).raw }
+    }
+
+    /// Takes the lock with `take`, which returns whether it succeeded, or fails with `error`.
+    fn take(
+        lock: &ArcPtr<c_void>,
+        mode: Mode,
+        take: impl FnOnce(&LockGuard) -> bool,
+        error: LockError,
+    ) -> Result<LockGuard, LockError> {
+        let mut guard = LockGuard {
+            lock: lock.clone(),
+            mode,
+            held: false,
+        };
+        let expected = match mode {
+            Mode::Lock => Kind::Mutex,
+            Mode::Read | Mode::Write => Kind::RwLock,
+        };
+        if guard.header().kind != expected {
+            return Err(LockError::WrongKind);
+        }
+        guard.held = take(&guard);
+        if !guard.held {
+            return Err(error);
+        }
+        // Dropping the guard releases the lock again.
+        guard.header().check().map(|_| guard)
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    pub fn unlock(&mut self) -> Result<(), LockError> {
+        if !self.held {
+            return Err(LockError::NotHeld);
+        }
+        self.held = false;
+        match self.mode {
+            Mode::Lock => self.mutex().unlock(),
+            Mode::Read => self.rwlock().unlock_shared(),
+            Mode::Write => self.rwlock().unlock_exclusive(),
+        }
+        Ok(())
+    }
+
+    /// Returns a pointer to the locked value, which must not be written through a read guard.
+    pub fn data(&self) -> Result<*mut c_void, LockError> {
+        match self.held {
+            true => Ok(unsafe {
+                (self.lock.as_ptr() as *mut u8).add(self.header().data_offset) as *mut c_void
+            }),
+            false => Err(LockError::NotHeld),
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = self.unlock();
+    }
+}
+
+fn throw_lock(field: &str, error: LockError, exception: &OutPtr<Exception>) {
+    let kind = match error {
+        LockError::Poisoned => ExceptionKind::Poisoned,
+        LockError::WrongKind => ExceptionKind::TypeMismatch,
+        _ => ExceptionKind::InvalidArgument,
+    };
+    let _: Nullable<()> = throw_kind(kind, alloc::format!("{}: {}", field, error), exception);
+}
+
+fn lock_guard(
+    lock: ArcPtr<c_void>,
+    mode: Mode,
+    take: impl FnOnce(&LockGuard) -> bool,
+    error: Option<LockError>,
+    exception: &OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    // The caller keeps its own reference, so it must not be released here.
+    let lock = core::mem::ManuallyDrop::new(lock);
+    if lock.is_null() {
+        return crate::exception::throw_null("lock", exception);
+    }
+    match LockGuard::take(&lock, mode, take, error.unwrap_or(LockError::WouldBlock)) {
+        Ok(guard) => Nullable::from(Box::new(guard)),
+        Err(e) if Some(e) == error => null(),
+        Err(e) => {
+            throw_lock("lock", e, exception);
+            null()
+        }
+    }
+}
+
+fn timeout(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Blocks until the `Mutex` is locked. Free the guard with `lock_guard_free`.
+#[no_mangle]
+pub extern "C" fn mutex_lock(
+    mutex: ArcPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| {
+            guard.mutex().lock();
+            true
+        };
+        lock_guard(mutex, Mode::Lock, take, None, &exception)
+    })
+}
+
+/// Locks the `Mutex` if it is not held elsewhere, or returns null without an exception.
+#[no_mangle]
+pub extern "C" fn mutex_try_lock(
+    mutex: ArcPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| guard.mutex().try_lock();
+        lock_guard(
+            mutex,
+            Mode::Lock,
+            take,
+            Some(LockError::WouldBlock),
+            &exception,
+        )
+    })
+}
+
+/// Locks the `Mutex`, or returns null without an exception if that takes over `timeout_ms`.
+#[no_mangle]
+pub extern "C" fn mutex_lock_timeout(
+    mutex: ArcPtr<c_void>,
+    timeout_ms: u64,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| guard.mutex().try_lock_for(timeout(timeout_ms));
+        lock_guard(
+            mutex,
+            Mode::Lock,
+            take,
+            Some(LockError::TimedOut),
+            &exception,
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rwlock_read(
+    rwlock: ArcPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| {
+            guard.rwlock().lock_shared();
+            true
+        };
+        lock_guard(rwlock, Mode::Read, take, None, &exception)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rwlock_try_read(
+    rwlock: ArcPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| guard.rwlock().try_lock_shared();
+        lock_guard(
+            rwlock,
+            Mode::Read,
+            take,
+            Some(LockError::WouldBlock),
+            &exception,
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rwlock_read_timeout(
+    rwlock: ArcPtr<c_void>,
+    timeout_ms: u64,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| guard.rwlock().try_lock_shared_for(timeout(timeout_ms));
+        lock_guard(
+            rwlock,
+            Mode::Read,
+            take,
+            Some(LockError::TimedOut),
+            &exception,
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rwlock_write(
+    rwlock: ArcPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| {
+            guard.rwlock().lock_exclusive();
+            true
+        };
+        lock_guard(rwlock, Mode::Write, take, None, &exception)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rwlock_try_write(
+    rwlock: ArcPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| guard.rwlock().try_lock_exclusive();
+        lock_guard(
+            rwlock,
+            Mode::Write,
+            take,
+            Some(LockError::WouldBlock),
+            &exception,
+        )
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn rwlock_write_timeout(
+    rwlock: ArcPtr<c_void>,
+    timeout_ms: u64,
+    exception: OutPtr<Exception>,
+) -> Nullable<LockGuard> {
+    ffi_boundary!(&exception, {
+        let take = |guard: &LockGuard| guard.rwlock().try_lock_exclusive_for(timeout(timeout_ms));
+        lock_guard(
+            rwlock,
+            Mode::Write,
+            take,
+            Some(LockError::TimedOut),
+            &exception,
+        )
+    })
+}
+
+/// Releases the lock held by `guard`, throwing if it has already been released.
+#[no_mangle]
+pub extern "C" fn lock_guard_unlock(mut guard: InOut<LockGuard>, exception: OutPtr<Exception>) {
+    ffi_boundary!(&exception, (), {
+        match unsafe { guard.as_mut_ref() } {
+            Some(guard) => {
+                if let Err(e) = guard.unlock() {
+                    throw_lock("guard", e, &exception);
+                }
+            }
+            None => {
+                let _: Nullable<()> = crate::exception::throw_null("guard", &exception);
+            }
+        }
+    })
+}
+
+/// Returns a pointer to the locked value, or null if `guard` has been released.
+#[no_mangle]
+pub extern "C" fn lock_guard_data(
+    mut guard: InOut<LockGuard>,
+    exception: OutPtr<Exception>,
+) -> *mut c_void {
+    ffi_boundary!(&exception, core::ptr::null_mut(), {
+        let guard = unsafe { try_as_mut_ref!(guard, &exception, core::ptr::null_mut()) };
+        match guard.data() {
+            Ok(data) => data,
+            Err(e) => {
+                throw_lock("guard", e, &exception);
+                core::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Frees `guard`, releasing its lock if it is still held.
+#[no_mangle]
+pub extern "C" fn lock_guard_free(guard: Nullable<LockGuard>) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        unsafe { guard.into_box() };
+    })
+}
+
+/// Returns whether the `Mutex` or `RwLock` is poisoned, or false if `lock` is null.
+#[no_mangle]
+pub extern "C" fn lock_is_poisoned(lock: ArcPtr<c_void>) -> bool {
+    let lock = core::mem::ManuallyDrop::new(lock);
+    match unsafe { (lock.as_ptr() as *const Header).as_ref() } {
+        Some(header) => header.poisoned.load(Ordering::Acquire),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn lock_clear_poison(lock: ArcPtr<c_void>) {
+    let lock = core::mem::ManuallyDrop::new(lock);
+    if let Some(header) = unsafe { (lock.as_ptr() as *const Header).as_ref() } {
+        header.poisoned.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr::NonNull;
+
+    fn kind(raw: *mut Exception) -> ExceptionKind {
+        unsafe { Exception::from_raw(NonNull::new(raw).unwrap()) }.kind()
+    }
+
+    #[test]
+    fn mutex() {
+        let mutex = Mutex::new(1u64);
+        {
+            let mut guard = mutex.lock().unwrap();
+            *guard += 1;
+            assert_eq!(mutex.try_lock().err(), Some(LockError::WouldBlock));
+            let timeout = Duration::from_millis(1);
+            assert_eq!(mutex.try_lock_for(timeout).err(), Some(LockError::TimedOut));
+        }
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+
+        let rwlock = RwLock::new(1u64);
+        let a = rwlock.read().unwrap();
+        let b = rwlock.try_read().unwrap();
+        assert_eq!(rwlock.try_write().err(), Some(LockError::WouldBlock));
+        drop((a, b));
+        *rwlock.write().unwrap() += 1;
+        assert_eq!(*rwlock.read().unwrap(), 2);
+    }
+
+    #[cfg(not(feature = "no-std"))]
+    #[test]
+    fn poisoning() {
+        let mutex = Mutex::new(0u64);
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = mutex.lock().unwrap();
+            panic!("oh no");
+        }));
+        assert!(mutex.is_poisoned());
+        assert_eq!(mutex.lock().err(), Some(LockError::Poisoned));
+        mutex.clear_poison();
+        assert!(mutex.try_lock().is_ok());
+    }
+
+    #[test]
+    fn ffi() {
+        let mutex = ArcPtr::new(Mutex::new(41u64));
+        let raw_mutex = || unsafe { ArcPtr::from_raw(mutex.as_ptr() as *const c_void) };
+        let mut raw = core::ptr::null_mut();
+
+        let mut guard = unsafe { mutex_lock(raw_mutex(), OutPtr::null()).into_box() }.unwrap();
+        assert!(mutex_try_lock(raw_mutex(), OutPtr::from(&mut raw)).is_null());
+        assert!(mutex_lock_timeout(raw_mutex(), 1, OutPtr::from(&mut raw)).is_null());
+        assert!(raw.is_null());
+        assert_eq!(mutex.strong_count(), 2);
+
+        let data = lock_guard_data(InOut::from(&mut *guard), OutPtr::null()) as *mut u64;
+        unsafe { *data += 1 };
+        lock_guard_unlock(InOut::from(&mut *guard), OutPtr::null());
+        lock_guard_unlock(InOut::from(&mut *guard), OutPtr::from(&mut raw));
+        assert_eq!(kind(raw), ExceptionKind::InvalidArgument);
+        assert!(lock_guard_data(InOut::from(&mut *guard), OutPtr::from(&mut raw)).is_null());
+        assert_eq!(kind(raw), ExceptionKind::InvalidArgument);
+        lock_guard_free(Nullable::from(guard));
+        assert_eq!(*mutex.try_lock().unwrap(), 42);
+
+        let guard = mutex_try_lock(raw_mutex(), OutPtr::null());
+        mutex.header.poisoned.store(true, Ordering::Release);
+        lock_guard_free(guard);
+        assert!(lock_is_poisoned(raw_mutex()));
+        assert!(mutex_lock(raw_mutex(), OutPtr::from(&mut raw)).is_null());
+        assert_eq!(kind(raw), ExceptionKind::Poisoned);
+        lock_clear_poison(raw_mutex());
+
+        assert!(rwlock_read(raw_mutex(), OutPtr::from(&mut raw)).is_null());
+        assert_eq!(kind(raw), ExceptionKind::TypeMismatch);
+        assert_eq!(mutex.strong_count(), 1);
+    }
+
+    #[test]
+    fn ffi_rwlock() {
+        let rwlock = ArcPtr::new(RwLock::new(0u8));
+        let raw_rwlock = || unsafe { ArcPtr::from_raw(rwlock.as_ptr() as *const c_void) };
+
+        let read = rwlock_read(raw_rwlock(), OutPtr::null());
+        let other = rwlock_try_read(raw_rwlock(), OutPtr::null());
+        assert!(!other.is_null());
+        assert!(rwlock_try_write(raw_rwlock(), OutPtr::null()).is_null());
+        assert!(rwlock_write_timeout(raw_rwlock(), 1, OutPtr::null()).is_null());
+        lock_guard_free(read);
+        lock_guard_free(other);
+
+        let write = rwlock_write(raw_rwlock(), OutPtr::null());
+        assert!(rwlock_read_timeout(raw_rwlock(), 1, OutPtr::null()).is_null());
+        lock_guard_free(write);
+        assert!(rwlock.try_write().is_ok());
+        assert_eq!(rwlock.strong_count(), 1);
+    }
+}
@@ -1,13 +1,14 @@
-use core::convert::TryFrom;
 use core::ptr::NonNull;
 
 use crate::exception::Exception;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "no-std")] {
-        use alloc::format;
-    } else {
-        use std::format;
+        #[doc(hidden)]
+        pub use crate::vendor::c_str::CStr;
+    } else if #[cfg(not(feature = "no-std"))] {
+        #[doc(hidden)]
+        pub use std::ffi::CStr;
     }
 }
 
@@ -18,17 +19,59 @@ pub fn not_null<T>(
     exception: &crate::inout::OutPtr<Exception>,
 ) -> Option<NonNull<T>> {
     if ptr.is_none() {
-        if let Some(exception) = exception.as_ptr() {
-            unsafe {
-                *exception.as_ptr() = Exception::try_from(&*format!("{} must not be null", field))
-                    .unwrap()
-                    .into_raw()
-            };
-        }
+        let _: crate::nullable::Nullable<()> = crate::exception::throw_null(field, exception);
     }
     ptr
 }
 
+#[inline]
+pub fn throw_slice(
+    field: &str,
+    error: crate::inout::SliceError,
+    exception: &crate::inout::OutPtr<Exception>,
+) {
+    let _: crate::nullable::Nullable<()> = match error {
+        crate::inout::SliceError::Null => crate::exception::throw_null(field, exception),
+        error => crate::exception::throw_kind(
+            crate::exception::ExceptionKind::InvalidArgument,
+            alloc::format!("{}: {}", field, error),
+            exception,
+        ),
+    };
+}
+
+#[cfg(feature = "handles")]
+#[inline]
+pub fn throw_handle(
+    field: &str,
+    error: crate::handles::HandleError,
+    exception: &crate::inout::OutPtr<Exception>,
+) {
+    use crate::exception::ExceptionKind;
+    use crate::handles::HandleError;
+
+    let kind = match error {
+        HandleError::Null => ExceptionKind::NullArgument,
+        HandleError::Stale => ExceptionKind::InvalidHandle,
+        HandleError::WrongType { .. } => ExceptionKind::TypeMismatch,
+    };
+    let _: crate::nullable::Nullable<()> =
+        crate::exception::throw_kind(kind, alloc::format!("{}: {}", field, error), exception);
+}
+
+/// Runs the body under `catch_panic`, so a panic is reported through `exception` rather than
+/// unwinding across the FFI. Returns `$fallback`, or `null()` if omitted, when a panic is caught.
+#[macro_export]
+macro_rules! ffi_boundary {
+    ($exception:expr, $fallback:expr, $body:block) => {
+        $crate::exception::catch_panic($exception, || $fallback, || $body)
+    };
+
+    ($exception:expr, $body:block) => {
+        $crate::exception::catch_panic($exception, $crate::nullable::null, || $body)
+    };
+}
+
 #[macro_export]
 macro_rules! try_not_null {
     ($path:expr, $exception:expr) => {
@@ -54,10 +97,8 @@ macro_rules! try_as_ref {
         match $arc.as_ref() {
             Some(r) => r,
             None => {
-                let _: Nullable<()> = $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($arc)),
-                    $exception,
-                );
+                let _: $crate::nullable::Nullable<()> =
+                    $crate::exception::throw_null(stringify!($arc), $exception);
 
                 return $fallback;
             }
@@ -68,10 +109,7 @@ macro_rules! try_as_ref {
         match $arc.as_ref() {
             Some(r) => r,
             None => {
-                return $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($arc)),
-                    $exception,
-                );
+                return $crate::exception::throw_null(stringify!($arc), $exception);
             }
         }
     };
@@ -83,10 +121,8 @@ macro_rules! try_as_mut_ref {
         match $thing.as_mut_ref() {
             Some(v) => v,
             None => {
-                let _: $crate::nullable::Nullable<()> = $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($thing)),
-                    $exception,
-                );
+                let _: $crate::nullable::Nullable<()> =
+                    $crate::exception::throw_null(stringify!($thing), $exception);
                 return $fallback;
             }
         }
@@ -96,39 +132,90 @@ macro_rules! try_as_mut_ref {
         match $thing.as_mut_ref() {
             Some(v) => v,
             None => {
-                return $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($thing)),
-                    $exception,
-                );
+                return $crate::exception::throw_null(stringify!($thing), $exception);
             }
         }
     };
 }
 
+/// Borrows an `InSlice<T>` or `OutSlice<T>` as a `&[T]`, throwing if it is null with a non-zero
+/// length or misaligned.
+#[macro_export]
+macro_rules! try_as_slice {
+    ($slice:expr, $exception:expr, $fallback:expr) => {
+        match $slice.as_slice() {
+            Ok(v) => v,
+            Err(e) => {
+                $crate::macros::throw_slice(stringify!($slice), e, $exception);
+                return $fallback;
+            }
+        }
+    };
+
+    ($slice:expr, $exception:expr) => {
+        $crate::try_as_slice!($slice, $exception, $crate::nullable::null())
+    };
+}
+
+/// Looks up a `Handle<T>`, throwing if it is null, stale or refers to another type.
+#[cfg(feature = "handles")]
+#[macro_export]
+macro_rules! try_as_handle {
+    ($handle:expr, $exception:expr, $fallback:expr) => {
+        match $handle.get() {
+            Ok(v) => v,
+            Err(e) => {
+                $crate::macros::throw_handle(stringify!($handle), e, $exception);
+                return $fallback;
+            }
+        }
+    };
+
+    ($handle:expr, $exception:expr) => {
+        $crate::try_as_handle!($handle, $exception, $crate::nullable::null())
+    };
+}
+
+/// Borrows an `OutSlice<T>` as a `&mut [T]`, throwing if it is null with a non-zero length or
+/// misaligned.
+#[macro_export]
+macro_rules! try_as_mut_slice {
+    ($slice:expr, $exception:expr, $fallback:expr) => {
+        match $slice.as_mut_slice() {
+            Ok(v) => v,
+            Err(e) => {
+                $crate::macros::throw_slice(stringify!($slice), e, $exception);
+                return $fallback;
+            }
+        }
+    };
+
+    ($slice:expr, $exception:expr) => {
+        $crate::try_as_mut_slice!($slice, $exception, $crate::nullable::null())
+    };
+}
+
+/// Takes ownership of the `ArcPtr` reference behind a pointer from `In::as_ptr`, throwing if
+/// it is null. Must be used in an unsafe block, as with `ArcPtr::from_raw`.
 #[macro_export]
 macro_rules! try_into_arc {
     ($arc:expr, $exception:expr) => {
         match $arc {
             None => {
-                return $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($arc)),
-                    $exception,
-                );
+                return $crate::exception::throw_null(stringify!($arc), $exception);
             }
-            Some(arc) => std::sync::Arc::from_raw(arc.as_ptr() as *const _),
+            Some(arc) => $crate::sync::ArcPtr::from_raw(arc.as_ptr() as *const _),
         }
     };
 
     ($arc:expr, $exception:expr, $fallback:expr) => {
         match $arc {
             None => {
-                let _: $crate::nullable::Nullable<()> = $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($arc)),
-                    $exception,
-                );
+                let _: $crate::nullable::Nullable<()> =
+                    $crate::exception::throw_null(stringify!($arc), $exception);
                 return $fallback;
             }
-            Some(arc) => std::sync::Arc::from_raw(arc.as_ptr() as *const _),
+            Some(arc) => $crate::sync::ArcPtr::from_raw(arc.as_ptr() as *const _),
         }
     };
 }
@@ -138,10 +225,7 @@ macro_rules! try_as_arc {
     ($inout:expr, $exception:expr) => {
         match $inout.as_arc() {
             None => {
-                return $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($arc)),
-                    $exception,
-                );
+                return $crate::exception::throw_null(stringify!($inout), $exception);
             }
             Some(arc) => arc,
         }
@@ -150,10 +234,8 @@ macro_rules! try_as_arc {
     ($inout:expr, $exception:expr, $fallback:expr) => {
         match $inout.as_arc() {
             None => {
-                let _: $crate::nullable::Nullable<()> = $crate::exception::throw_message(
-                    &*format!("{} must not be null", stringify!($arc)),
-                    $exception,
-                );
+                let _: $crate::nullable::Nullable<()> =
+                    $crate::exception::throw_null(stringify!($inout), $exception);
                 return $fallback;
             }
             Some(arc) => arc,
@@ -161,11 +243,38 @@ macro_rules! try_as_arc {
     };
 }
 
+/// Reads a NUL-terminated `In<c_char>`, throwing if it is null.
+///
+/// By default the string must be valid UTF-8 and a `&str` is produced, throwing otherwise. With
+/// `lossy`, invalid sequences are replaced with U+FFFD to produce a `Cow<str>`, and with `bytes`
+/// the `&[u8]` is produced without any validation.
 #[macro_export]
 macro_rules! try_as_str {
+    ($ptr:expr, lossy, $exception:expr, $fallback:expr) => {
+        match $crate::macros::not_null(stringify!($ptr), $ptr.as_ptr(), $exception) {
+            Some(ptr) => unsafe { $crate::macros::CStr::from_ptr(ptr.as_ptr()) }.to_string_lossy(),
+            None => return $fallback,
+        }
+    };
+
+    ($ptr:expr, lossy, $exception:expr) => {
+        $crate::try_as_str!($ptr, lossy, $exception, $crate::nullable::null())
+    };
+
+    ($ptr:expr, bytes, $exception:expr, $fallback:expr) => {
+        match $crate::macros::not_null(stringify!($ptr), $ptr.as_ptr(), $exception) {
+            Some(ptr) => unsafe { $crate::macros::CStr::from_ptr(ptr.as_ptr()) }.to_bytes(),
+            None => return $fallback,
+        }
+    };
+
+    ($ptr:expr, bytes, $exception:expr) => {
+        $crate::try_as_str!($ptr, bytes, $exception, $crate::nullable::null())
+    };
+
     ($ptr:expr, $exception:expr, $fallback:expr) => {
         match $crate::macros::not_null(stringify!($ptr), $ptr.as_ptr(), $exception) {
-            Some(ptr) => match unsafe { std::ffi::CStr::from_ptr(ptr.as_ptr()).to_str() } {
+            Some(ptr) => match unsafe { $crate::macros::CStr::from_ptr(ptr.as_ptr()) }.to_str() {
                 Ok(v) => v,
                 Err(e) => {
                     let _: $crate::nullable::Nullable<()> = $crate::exception::throw(e, $exception);
@@ -175,13 +284,134 @@ macro_rules! try_as_str {
             None => return $fallback,
         }
     };
+
     ($ptr:expr, $exception:expr) => {
+        $crate::try_as_str!($ptr, $exception, $crate::nullable::null())
+    };
+}
+
+/// Reads a UTF-16 `In<u16>` into a `String`, throwing on null or an unpaired surrogate.
+///
+/// The string is NUL-terminated, or exactly `len = $len` code units long, in which case it may
+/// be null when `$len` is 0.
+#[macro_export]
+macro_rules! try_as_wide_str {
+    ($ptr:expr, len = $len:expr, $exception:expr, $fallback:expr) => {{
+        let len: usize = $len;
+        let string = match ($ptr.as_ptr(), len) {
+            (None, 0) => Ok(::core::default::Default::default()),
+            (ptr, len) => match $crate::macros::not_null(stringify!($ptr), ptr, $exception) {
+                Some(ptr) => unsafe { $crate::wide::WideStr::from_raw_parts(ptr, len) }.to_string(),
+                None => return $fallback,
+            },
+        };
+        match string {
+            Ok(v) => v,
+            Err(e) => {
+                let _: $crate::nullable::Nullable<()> = $crate::exception::throw(e, $exception);
+                return $fallback;
+            }
+        }
+    }};
+
+    ($ptr:expr, len = $len:expr, $exception:expr) => {
+        $crate::try_as_wide_str!($ptr, len = $len, $exception, $crate::nullable::null())
+    };
+
+    ($ptr:expr, $exception:expr, $fallback:expr) => {
         match $crate::macros::not_null(stringify!($ptr), $ptr.as_ptr(), $exception) {
-            Some(ptr) => match unsafe { std::ffi::CStr::from_ptr(ptr.as_ptr()).to_str() } {
+            Some(ptr) => match unsafe { $crate::wide::WideStr::from_ptr(ptr) }.to_string() {
                 Ok(v) => v,
-                Err(e) => return throw(e, $exception),
+                Err(e) => {
+                    let _: $crate::nullable::Nullable<()> = $crate::exception::throw(e, $exception);
+                    return $fallback;
+                }
             },
-            None => return $crate::nullable::null(),
+            None => return $fallback,
         }
     };
+
+    ($ptr:expr, $exception:expr) => {
+        $crate::try_as_wide_str!($ptr, $exception, $crate::nullable::null())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exception::{Exception, ExceptionKind};
+    use crate::inout::{In, InSlice, OutPtr, OutSlice};
+    use alloc::borrow::Cow;
+    use libc::c_char;
+
+    fn strict(s: In<c_char>, exception: OutPtr<Exception>) -> Option<&'static str> {
+        Some(try_as_str!(s, &exception, None))
+    }
+
+    fn lossy(s: In<c_char>, exception: OutPtr<Exception>) -> Option<Cow<'static, str>> {
+        Some(try_as_str!(s, lossy, &exception, None))
+    }
+
+    fn bytes(s: In<c_char>, exception: OutPtr<Exception>) -> Option<&'static [u8]> {
+        Some(try_as_str!(s, bytes, &exception, None))
+    }
+
+    fn sum(values: InSlice<u32>, exception: OutPtr<Exception>) -> u32 {
+        unsafe { try_as_slice!(values, &exception, 0) }.iter().sum()
+    }
+
+    fn double(mut values: OutSlice<u32>, exception: OutPtr<Exception>) -> bool {
+        for value in unsafe { try_as_mut_slice!(values, &exception, false) } {
+            *value *= 2;
+        }
+        true
+    }
+
+    fn kind(f: impl FnOnce(OutPtr<Exception>)) -> Option<ExceptionKind> {
+        let mut exception = core::ptr::null_mut();
+        f(OutPtr::from(&mut exception));
+        let e = core::ptr::NonNull::new(exception)?;
+        Some(unsafe { Exception::from_raw(e) }.kind())
+    }
+
+    #[test]
+    fn slices() {
+        let mut values = [1u32, 2, 3];
+        assert!(double(OutSlice::from(&mut values[..]), OutPtr::null()));
+        assert_eq!(sum(InSlice::from(&values[..]), OutPtr::null()), 12);
+        assert_eq!(sum(InSlice::null(), OutPtr::null()), 0);
+
+        let null = OutSlice::from_raw_parts(core::ptr::null_mut(), 3);
+        assert_eq!(
+            kind(|e| assert!(!double(null, e))),
+            Some(ExceptionKind::NullArgument)
+        );
+        let null = InSlice::from_raw_parts(core::ptr::null(), 3);
+        assert_eq!(
+            kind(|e| assert_eq!(sum(null, e), 0)),
+            Some(ExceptionKind::NullArgument)
+        );
+
+        let words = [0u32; 4];
+        let misaligned = unsafe { (words.as_ptr() as *const u8).add(1) } as *const u32;
+        let misaligned = InSlice::from_raw_parts(misaligned, 2);
+        assert_eq!(
+            kind(|e| assert_eq!(sum(misaligned, e), 0)),
+            Some(ExceptionKind::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn str_modes() {
+        static INVALID: [u8; 3] = [b'a', 0xff, 0];
+        let input = || In::from(&INVALID[0]).cast::<c_char>();
+
+        let mut exception = core::ptr::null_mut();
+        assert!(strict(input(), OutPtr::from(&mut exception)).is_none());
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::InvalidUtf8);
+
+        assert_eq!(lossy(input(), OutPtr::null()).unwrap(), "a\u{fffd}");
+        assert_eq!(bytes(input(), OutPtr::null()).unwrap(), b"a\xff");
+        assert!(bytes(In::null(), OutPtr::null()).is_none());
+    }
 }
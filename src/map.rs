@@ -0,0 +1,271 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec as RealVec};
+use core::{any::Any, ffi::c_void, hash::Hash, marker::PhantomData};
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+
+use crate::{sync::ArcPtr, vec::Vec};
+
+pub mod ffi;
+
+type Table<K, V> = RwLock<HashMap<K, ArcPtr<V>>>;
+
+/// Storage for a `Map`, erased so that it may be used without its types.
+trait Storage: Any + Send + Sync {
+    fn len(&self) -> usize;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<K, V> Storage for Table<K, V>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct RawMap(Arc<dyn Storage>);
+
+impl RawMap {
+    #[inline]
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the map as a `Map<K, V>`, or `None` if it holds other types.
+    #[inline]
+    pub fn downcast_ref<K, V>(&self) -> Option<&Map<K, V>>
+    where
+        K: Eq + Hash + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        match self.0.as_any().is::<Table<K, V>>() {
+            true => Some(unsafe { &*(self as *const RawMap as *const Map<K, V>) }),
+            false => None,
+        }
+    }
+}
+
+impl core::fmt::Debug for RawMap {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "RawMap({} entries)", self.len())
+    }
+}
+
+/// A hash map that can be shared with foreign code, like `Vec`.
+///
+/// Values are held as `ArcPtr<V>`, so foreign code can keep one after it has been removed.
+/// Like `Vec`, clones share the same map.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct Map<K, V>(RawMap, PhantomData<(K, V)>);
+
+impl<K, V> Clone for Map<K, V> {
+    fn clone(&self) -> Map<K, V> {
+        Map(self.0.clone(), PhantomData)
+    }
+}
+
+impl<K, V> Map<K, V>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn new() -> Map<K, V> {
+        let table: Table<K, V> = RwLock::new(HashMap::new());
+        Map(RawMap(Arc::new(table)), PhantomData)
+    }
+
+    #[inline]
+    fn table(&self) -> &Table<K, V> {
+        self.0
+             .0
+            .as_any()
+            .downcast_ref()
+            .expect("storage matches type")
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<ArcPtr<V>> {
+        self.table().write().insert(key, ArcPtr::new(value))
+    }
+
+    pub fn get(&self, key: &K) -> Option<ArcPtr<V>> {
+        self.table().read().get(key).cloned()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.table().read().contains_key(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<ArcPtr<V>> {
+        self.table().write().remove(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.table().write().clear();
+    }
+
+    /// Returns the keys, in no particular order.
+    pub fn keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let mut keys = Vec::new();
+        for key in self.table().read().keys() {
+            keys.push(key.clone());
+        }
+        keys
+    }
+
+    /// Iterates over a snapshot of the entries, in no particular order.
+    pub fn iter(&self) -> MapIter<K, V>
+    where
+        K: Clone,
+    {
+        let entries: RealVec<_> = self
+            .table()
+            .read()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        MapIter(entries.into_iter())
+    }
+
+    pub fn into_raw(self) -> *const Map<K, V> {
+        let ptr = Box::into_raw(Box::new(self.0)) as *const _;
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "Map", core::any::type_name::<(K, V)>());
+        ptr
+    }
+
+    /// Reclaims a map previously returned by `into_raw` or `map_new`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live map of `K` to `V` from `into_raw` or `map_new`, which must not
+    /// be used again once reclaimed.
+    pub unsafe fn from_raw(ptr: *const Map<K, V>) -> Map<K, V> {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr);
+        let raw_map: Box<RawMap> = Box::from_raw(ptr as *mut _);
+        assert!(raw_map.0.as_any().is::<Table<K, V>>());
+        Map(*raw_map, PhantomData)
+    }
+}
+
+impl<K, V> Default for Map<K, V>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    fn default() -> Map<K, V> {
+        Map::new()
+    }
+}
+
+impl<K, V> IntoIterator for &Map<K, V>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    type Item = (K, ArcPtr<V>);
+    type IntoIter = MapIter<K, V>;
+
+    fn into_iter(self) -> MapIter<K, V> {
+        self.iter()
+    }
+}
+
+/// An iterator over a snapshot of a `Map`.
+pub struct MapIter<K, V>(alloc::vec::IntoIter<(K, ArcPtr<V>)>);
+
+impl<K, V> Iterator for MapIter<K, V> {
+    type Item = (K, ArcPtr<V>);
+
+    fn next(&mut self) -> Option<(K, ArcPtr<V>)> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+/// An iterator over a snapshot of a map's entries for foreign code, with each key copied into
+/// its own `ArcPtr` so the entries can be read without knowing their types.
+pub struct RawMapIter(alloc::vec::IntoIter<(ArcPtr<c_void>, ArcPtr<c_void>)>);
+
+impl RawMapIter {
+    fn new<K, V>(map: &Map<K, V>) -> RawMapIter
+    where
+        K: Clone + Eq + Hash + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let entries: RealVec<_> = map
+            .iter()
+            .map(|(key, value)| (ArcPtr::new(key).erase(), value.erase()))
+            .collect();
+        RawMapIter(entries.into_iter())
+    }
+}
+
+impl Iterator for RawMapIter {
+    type Item = (ArcPtr<c_void>, ArcPtr<c_void>);
+
+    fn next(&mut self) -> Option<(ArcPtr<c_void>, ArcPtr<c_void>)> {
+        self.0.next()
+    }
+}
+
+impl core::fmt::Debug for RawMapIter {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "RawMapIter({} entries left)", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn map() {
+        let mut map = Map::new();
+        assert!(map.insert(String::from("a"), 1u64).is_none());
+        map.insert(String::from("b"), 2);
+        assert_eq!(*map.insert(String::from("a"), 3).unwrap(), 1);
+        assert_eq!(map.len(), 2);
+
+        let a = map.get(&String::from("a")).unwrap();
+        assert_eq!(*map.remove(&String::from("a")).unwrap(), 3);
+        assert_eq!(*a, 3);
+        assert!(!map.contains_key(&String::from("a")));
+
+        let mut keys = map.keys().to_owned_vec();
+        keys.sort();
+        assert_eq!(keys, ["b"]);
+
+        let shared = map.clone();
+        map.insert(String::from("c"), 4);
+        let mut total = 0;
+        for (_, value) in &shared {
+            total += *value;
+        }
+        assert_eq!(total, 6);
+    }
+}
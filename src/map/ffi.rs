@@ -0,0 +1,474 @@
+use core::{ffi::c_void, hash::Hash};
+
+use alloc::boxed::Box;
+
+use super::{Map, RawMap, RawMapIter};
+use crate::{
+    exception::{throw_null, Exception},
+    inout::{In, InOut, InRaw, OutPtr},
+    nullable::Nullable,
+    sync::ArcPtr,
+    vec::RawVec,
+};
+
+#[no_mangle]
+pub extern "C" fn map_len(handle: In<RawMap>, exception: OutPtr<Exception>) -> usize {
+    ffi_boundary!(&exception, 0, {
+        let handle = unsafe { try_as_ref!(handle, &exception, 0) };
+        handle.len()
+    })
+}
+
+/// Borrows a key argument as a `K`, throwing if it is null.
+unsafe fn key<'a, K>(key: &InRaw, exception: &OutPtr<Exception>) -> Option<&'a K> {
+    match key.as_ptr() {
+        Some(key) => Some(&*(key.as_ptr() as *const K)),
+        None => {
+            let _: Nullable<()> = throw_null("key", exception);
+            None
+        }
+    }
+}
+
+// The functions below are called by `generate_map_ffi!` once it has matched the type tag. Each
+// returns `None` if the map holds other types.
+
+/// # Safety
+///
+/// `key` and `value` must be null or point to a valid `K` and `V`.
+#[doc(hidden)]
+pub unsafe fn insert<K, V>(
+    handle: &RawMap,
+    key: &InRaw,
+    value: &InRaw,
+    exception: &OutPtr<Exception>,
+) -> Option<bool>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    let mut map = handle.downcast_ref::<K, V>()?.clone();
+    let key = match self::key::<K>(key, exception) {
+        Some(key) => key,
+        None => return Some(false),
+    };
+    let value = match value.as_ptr() {
+        Some(value) => (*(value.as_ptr() as *const V)).clone(),
+        None => {
+            let _: Nullable<()> = throw_null("value", exception);
+            return Some(false);
+        }
+    };
+    Some(map.insert(key.clone(), value).is_some())
+}
+
+/// # Safety
+///
+/// `key` must be null or point to a valid `K`.
+#[doc(hidden)]
+pub unsafe fn get<K, V>(
+    handle: &RawMap,
+    key: &InRaw,
+    exception: &OutPtr<Exception>,
+) -> Option<ArcPtr<c_void>>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    let map = handle.downcast_ref::<K, V>()?;
+    let key = match self::key::<K>(key, exception) {
+        Some(key) => key,
+        None => return Some(ArcPtr::null()),
+    };
+    Some(map.get(key).map(ArcPtr::erase).unwrap_or_else(ArcPtr::null))
+}
+
+/// # Safety
+///
+/// `key` must be null or point to a valid `K`.
+#[doc(hidden)]
+pub unsafe fn remove<K, V>(
+    handle: &RawMap,
+    key: &InRaw,
+    exception: &OutPtr<Exception>,
+) -> Option<ArcPtr<c_void>>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    let mut map = handle.downcast_ref::<K, V>()?.clone();
+    let key = match self::key::<K>(key, exception) {
+        Some(key) => key,
+        None => return Some(ArcPtr::null()),
+    };
+    let removed = map.remove(key);
+    Some(removed.map(ArcPtr::erase).unwrap_or_else(ArcPtr::null))
+}
+
+/// Frees the map, returning `false` without freeing it if it holds other types.
+///
+/// # Safety
+///
+/// `handle` must come from `map_new` and must not be used again if it is freed.
+#[doc(hidden)]
+pub unsafe fn free<K, V>(handle: *const RawMap) -> bool
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    if (*handle).downcast_ref::<K, V>().is_none() {
+        return false;
+    }
+    drop(Map::<K, V>::from_raw(handle as *const Map<K, V>));
+    true
+}
+
+#[doc(hidden)]
+pub fn keys<K, V>(handle: &RawMap) -> Option<Nullable<RawVec>>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    let map = handle.downcast_ref::<K, V>()?;
+    let keys = map.keys().into_raw();
+    Some(Nullable::from_owned_raw(keys as *const RawVec))
+}
+
+#[doc(hidden)]
+pub fn iter<K, V>(handle: &RawMap) -> Option<Nullable<RawMapIter>>
+where
+    K: Clone + Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    let map = handle.downcast_ref::<K, V>()?;
+    Some(Nullable::from(Box::new(RawMapIter::new(map))))
+}
+
+/// Returns the next value, to be released with `arc_drop`, writing its key to `*key`, which is
+/// also released with `arc_drop`. Returns null at the end, leaving `*key` unchanged.
+#[no_mangle]
+pub extern "C" fn map_iter_next(
+    mut iter: InOut<RawMapIter>,
+    mut key: OutPtr<c_void>,
+    exception: OutPtr<Exception>,
+) -> ArcPtr<c_void> {
+    ffi_boundary!(&exception, ArcPtr::null(), {
+        let iter = unsafe { try_as_mut_ref!(iter, &exception, ArcPtr::null()) };
+        let key = unsafe { try_as_mut_ref!(key, &exception, ArcPtr::null()) };
+        match iter.next() {
+            Some((next_key, value)) => {
+                *key = next_key.into_raw() as *mut c_void;
+                value
+            }
+            None => ArcPtr::null(),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn map_iter_free(iter: Nullable<RawMapIter>) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        unsafe { iter.into_box() };
+    })
+}
+
+/// Generates the type-dependent map exports for the given key and value types.
+///
+/// Each `NAME => Key => Value` triple exports a `TypeTag` constant `NAME`, which foreign code
+/// passes to `map_new`, `map_insert`, `map_get`, `map_remove`, `map_keys`, `map_iter_new` and
+/// `map_free` to select the types. Keys and values are copied in with `Clone`. Values are returned
+/// as an `ArcPtr`, to be released with `arc_drop`.
+#[macro_export]
+macro_rules! generate_map_ffi {
+    { $( $ty_name:ident => $key:ty => $value:ty ),* } => {
+        $(
+            #[no_mangle]
+            pub static $ty_name: $crate::tag::TypeTag = $crate::tag::TypeTag::from_name(stringify!($ty_name));
+        )*
+
+        #[no_mangle]
+        pub extern "C" fn map_new(ty: $crate::tag::TypeTag) -> $crate::nullable::Nullable<$crate::map::RawMap> {
+            $crate::ffi_boundary!(&$crate::inout::OutPtr::null(), {
+                $(
+                    if ty == $ty_name {
+                        let map = $crate::map::Map::<$key, $value>::new().into_raw();
+                        return $crate::nullable::Nullable::from_owned_raw(map as *const $crate::map::RawMap);
+                    }
+                )*
+                $crate::nullable::null()
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C" fn map_free(
+            handle: $crate::nullable::Nullable<$crate::map::RawMap>,
+            ty: $crate::tag::TypeTag,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) {
+            $crate::ffi_boundary!(&exception, (), {
+                if handle.is_null() {
+                    let _: $crate::nullable::Nullable<()> = $crate::exception::throw_null("handle", &exception);
+                    return;
+                }
+
+                let handle = handle.into_raw();
+                $(
+                    if ty == $ty_name && unsafe { $crate::map::ffi::free::<$key, $value>(handle) } {
+                        return;
+                    }
+                )*
+
+                let _: $crate::nullable::Nullable<()> = $crate::map::ffi::type_mismatch(&exception);
+            })
+        }
+
+        /// Inserts copies of `*key` and `*value`, returning whether a value was replaced.
+        #[no_mangle]
+        pub extern "C" fn map_insert(
+            handle: $crate::inout::In<$crate::map::RawMap>,
+            ty: $crate::tag::TypeTag,
+            key: $crate::inout::InRaw,
+            value: $crate::inout::InRaw,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) -> bool {
+            $crate::ffi_boundary!(&exception, false, {
+                let handle = unsafe { $crate::try_as_ref!(handle, &exception, false) };
+                $(
+                    if ty == $ty_name {
+                        let result = unsafe {
+                            $crate::map::ffi::insert::<$key, $value>(handle, &key, &value, &exception)
+                        };
+                        if let Some(replaced) = result {
+                            return replaced;
+                        }
+                    }
+                )*
+                let _: $crate::nullable::Nullable<()> = $crate::map::ffi::type_mismatch(&exception);
+                false
+            })
+        }
+
+        /// Returns a new reference to the value for `*key`, or null if there is none.
+        #[no_mangle]
+        pub extern "C" fn map_get(
+            handle: $crate::inout::In<$crate::map::RawMap>,
+            ty: $crate::tag::TypeTag,
+            key: $crate::inout::InRaw,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) -> $crate::sync::ArcPtr<core::ffi::c_void> {
+            $crate::ffi_boundary!(&exception, $crate::sync::ArcPtr::null(), {
+                let handle = unsafe { $crate::try_as_ref!(handle, &exception, $crate::sync::ArcPtr::null()) };
+                $(
+                    if ty == $ty_name {
+                        if let Some(value) = unsafe { $crate::map::ffi::get::<$key, $value>(handle, &key, &exception) } {
+                            return value;
+                        }
+                    }
+                )*
+                let _: $crate::nullable::Nullable<()> = $crate::map::ffi::type_mismatch(&exception);
+                $crate::sync::ArcPtr::null()
+            })
+        }
+
+        /// Removes the value for `*key`, returning it, or null if there was none.
+        #[no_mangle]
+        pub extern "C" fn map_remove(
+            handle: $crate::inout::In<$crate::map::RawMap>,
+            ty: $crate::tag::TypeTag,
+            key: $crate::inout::InRaw,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) -> $crate::sync::ArcPtr<core::ffi::c_void> {
+            $crate::ffi_boundary!(&exception, $crate::sync::ArcPtr::null(), {
+                let handle = unsafe { $crate::try_as_ref!(handle, &exception, $crate::sync::ArcPtr::null()) };
+                $(
+                    if ty == $ty_name {
+                        if let Some(value) = unsafe { $crate::map::ffi::remove::<$key, $value>(handle, &key, &exception) } {
+                            return value;
+                        }
+                    }
+                )*
+                let _: $crate::nullable::Nullable<()> = $crate::map::ffi::type_mismatch(&exception);
+                $crate::sync::ArcPtr::null()
+            })
+        }
+
+        /// Returns the keys as a new `Vec`, to be freed with `vec_free` and the key type's tag.
+        #[no_mangle]
+        pub extern "C" fn map_keys(
+            handle: $crate::inout::In<$crate::map::RawMap>,
+            ty: $crate::tag::TypeTag,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) -> $crate::nullable::Nullable<$crate::vec::RawVec> {
+            $crate::ffi_boundary!(&exception, {
+                let handle = unsafe { $crate::try_as_ref!(handle, &exception) };
+                $(
+                    if ty == $ty_name {
+                        if let Some(keys) = $crate::map::ffi::keys::<$key, $value>(handle) {
+                            return keys;
+                        }
+                    }
+                )*
+                $crate::map::ffi::type_mismatch(&exception)
+            })
+        }
+
+        /// Creates an iterator over a snapshot of the entries, taking the map's lock once. Free
+        /// with `map_iter_free`.
+        #[no_mangle]
+        pub extern "C" fn map_iter_new(
+            handle: $crate::inout::In<$crate::map::RawMap>,
+            ty: $crate::tag::TypeTag,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) -> $crate::nullable::Nullable<$crate::map::RawMapIter> {
+            $crate::ffi_boundary!(&exception, {
+                let handle = unsafe { $crate::try_as_ref!(handle, &exception) };
+                $(
+                    if ty == $ty_name {
+                        if let Some(iter) = $crate::map::ffi::iter::<$key, $value>(handle) {
+                            return iter;
+                        }
+                    }
+                )*
+                $crate::map::ffi::type_mismatch(&exception)
+            })
+        }
+    };
+}
+
+#[doc(hidden)]
+pub fn type_mismatch<T>(exception: &OutPtr<Exception>) -> Nullable<T> {
+    crate::exception::throw_kind(
+        crate::exception::ExceptionKind::TypeMismatch,
+        "unknown type tag, or the map holds other types",
+        exception,
+    )
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "demo")] {
+        generate_map_ffi! {
+            MAP_STRING_U64 => std::string::String => u64
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "demo"))]
+mod tests {
+    use super::{map_iter_free, map_iter_next, map_len};
+    use crate::prelude::*;
+    use alloc::string::String;
+
+    mod generated {
+        crate::generate_map_ffi! {
+            TEST_MAP_STRING_U64 => alloc::string::String => u64,
+            TEST_MAP_U8_U8 => u8 => u8
+        }
+    }
+    use generated::*;
+
+    fn raw<T>(value: &T) -> InRaw {
+        InRaw::from(unsafe { &*(value as *const T as *const core::ffi::c_void) })
+    }
+
+    #[test]
+    fn ffi() {
+        let handle = map_new(TEST_MAP_STRING_U64).into_raw();
+        let map = || In::from(unsafe { &*handle });
+        let (a, b) = (String::from("a"), String::from("b"));
+        let mut exception = core::ptr::null_mut();
+
+        let ty = TEST_MAP_STRING_U64;
+        assert!(!map_insert(map(), ty, raw(&a), raw(&1u64), OutPtr::null()));
+        assert!(map_insert(map(), ty, raw(&a), raw(&2u64), OutPtr::null()));
+        assert!(!map_insert(map(), ty, raw(&b), raw(&3u64), OutPtr::null()));
+        assert_eq!(map_len(map(), OutPtr::null()), 2);
+
+        let value = map_get(map(), ty, raw(&a), OutPtr::null());
+        assert!(!value.is_null());
+        assert_eq!(*unsafe { value.cast::<u64>() }, 2);
+        let removed = map_remove(map(), ty, raw(&b), OutPtr::null());
+        assert_eq!(*unsafe { removed.cast::<u64>() }, 3);
+        assert!(map_get(map(), ty, raw(&b), OutPtr::null()).is_null());
+
+        let keys = map_keys(map(), ty, OutPtr::null()).into_raw();
+        let keys = unsafe { Vec::<String>::from_raw(keys as *const _) };
+        assert_eq!(keys.to_owned_vec(), [a]);
+
+        assert!(map_get(
+            map(),
+            TEST_MAP_U8_U8,
+            raw(&0u8),
+            OutPtr::from(&mut exception)
+        )
+        .is_null());
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::TypeMismatch);
+        map_get(map(), ty, InRaw::null(), OutPtr::from(&mut exception));
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::NullArgument);
+
+        let handle = || unsafe { Nullable::from_raw(handle) };
+        map_free(handle(), TEST_MAP_U8_U8, OutPtr::from(&mut exception));
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::TypeMismatch);
+        map_free(handle(), ty, OutPtr::null());
+    }
+
+    #[test]
+    fn iter() {
+        let handle = map_new(TEST_MAP_STRING_U64).into_raw();
+        let map = || In::from(unsafe { &*handle });
+        let ty = TEST_MAP_STRING_U64;
+        map_insert(
+            map(),
+            ty,
+            raw(&String::from("a")),
+            raw(&1u64),
+            OutPtr::null(),
+        );
+        map_insert(
+            map(),
+            ty,
+            raw(&String::from("b")),
+            raw(&2u64),
+            OutPtr::null(),
+        );
+
+        let mut iter = unsafe { map_iter_new(map(), ty, OutPtr::null()).into_box() }.unwrap();
+        // Inserting after the snapshot is taken does not affect it.
+        map_insert(
+            map(),
+            ty,
+            raw(&String::from("c")),
+            raw(&3u64),
+            OutPtr::null(),
+        );
+
+        let mut entries = alloc::vec::Vec::new();
+        loop {
+            let mut key = core::ptr::null_mut();
+            let value = map_iter_next(
+                InOut::from(&mut *iter),
+                OutPtr::from(&mut key),
+                OutPtr::null(),
+            );
+            if value.is_null() {
+                assert!(key.is_null());
+                break;
+            }
+            let key = unsafe { ArcPtr::from_raw(key as *const core::ffi::c_void).cast::<String>() };
+            let value = unsafe { value.cast::<u64>() };
+            entries.push(((*key).clone(), *value));
+        }
+        entries.sort();
+        assert_eq!(entries, [(String::from("a"), 1), (String::from("b"), 2)]);
+        map_iter_free(Nullable::from(iter));
+
+        let mut exception = core::ptr::null_mut();
+        assert!(map_iter_new(map(), TEST_MAP_U8_U8, OutPtr::from(&mut exception)).is_null());
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::TypeMismatch);
+        map_free(unsafe { Nullable::from_raw(handle) }, ty, OutPtr::null());
+    }
+}
@@ -1,66 +1,132 @@
-use crate::sync::ArcPtr;
+use alloc::boxed::Box;
 
+/// A nullable pointer for returning values over the FFI.
+///
+/// A `Nullable<T>` built from a `Box<T>`, an `Option<T>` or `from_owned_raw` owns a boxed `T`,
+/// which must be given back to Rust to be freed. This holds for every `T`, so a
+/// `Nullable<ArcPtr<T>>` is a boxed `ArcPtr`; shared values are instead returned as an `ArcPtr<T>`
+/// or `WeakPtr<T>`, which may itself be null.
 #[repr(transparent)]
 pub struct Nullable<T>(*const T);
 
 impl<T> Nullable<T> {
+    /// Wraps a pointer without taking ownership of it, such as a pointer borrowed from a parent.
     pub fn new(ptr: *const T) -> Nullable<T> {
         Nullable(ptr)
     }
+
+    /// Takes ownership of a boxed `T` from an `into_raw` such as `Vec::into_raw`, which records it
+    /// with the leak tracker and so must be freed through the matching `from_raw` rather than
+    /// `into_box`.
+    pub fn from_owned_raw(ptr: *const T) -> Nullable<T> {
+        Nullable(ptr)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+
+    pub fn into_raw(self) -> *const T {
+        self.0
+    }
+
+    /// Reclaims a pointer previously returned by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or come from `into_raw` on a `Nullable<T>`.
+    pub unsafe fn from_raw(ptr: *const T) -> Nullable<T> {
+        Nullable(ptr)
+    }
+
+    /// Reclaims the box owned by this pointer, if it is not null.
+    ///
+    /// # Safety
+    ///
+    /// The `Nullable` must have been built from a `Box<T>` or `Option<T>`, and its box must not
+    /// have been reclaimed already.
+    pub unsafe fn into_box(self) -> Option<Box<T>> {
+        match self.is_null() {
+            true => None,
+            false => Some(Box::from_raw(self.0 as *mut T)),
+        }
+    }
 }
 
 pub fn null<T>() -> Nullable<T> {
     Nullable(core::ptr::null())
 }
 
-impl<T> From<ArcPtr<T>> for Nullable<ArcPtr<T>> {
-    fn from(thing: ArcPtr<T>) -> Nullable<ArcPtr<T>> {
-        Nullable(&thing)
+impl<T> From<Box<T>> for Nullable<T> {
+    fn from(boxed: Box<T>) -> Nullable<T> {
+        Nullable(Box::into_raw(boxed))
     }
 }
 
 impl<T> From<Option<T>> for Nullable<T> {
     fn from(option: Option<T>) -> Nullable<T> {
         match option {
-            Some(value) => Nullable(&value),
+            Some(value) => Nullable::from(Box::new(value)),
             None => null(),
         }
     }
 }
 
-// impl<T> From<Option<ArcPtr<T>>> for Nullable<T> {
-//     fn from(option: Option<ArcPtr<T>>) -> Nullable<T> {
-//         match option {
-//             Some(value) => Nullable(Arc::into_raw(value)),
-//             None => null()
-//         }
-//     }
-// }
-
+// These tests, like those in `sync`, are meant to pass under Miri:
+// `cargo +nightly miri test -p cursed --lib -- nullable sync`.
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sync::ArcPtr;
     use alloc::string::String;
 
+    #[derive(Debug, PartialEq)]
     struct TestStruct {
         field1: u64,
         field2: String,
     }
 
-    #[test]
-    fn is_not_null() {
-        let thing = Some(TestStruct {
+    fn returned() -> Nullable<TestStruct> {
+        Nullable::from(Some(TestStruct {
             field1: 523,
             field2: "oh no".into(),
-        });
-        let nullable = Nullable::from(thing);
-        assert_ne!(nullable.0, null::<TestStruct>().0);
+        }))
+    }
+
+    #[test]
+    fn is_not_null() {
+        let nullable = returned();
+        assert!(!nullable.is_null());
+
+        let value = unsafe { Nullable::from_raw(nullable.into_raw()).into_box() };
+        assert_eq!(value.unwrap().field2, "oh no");
     }
 
     #[test]
     fn is_null() {
         let thing = None;
-        let nullable = Nullable::from(thing);
-        assert_eq!(nullable.0, null::<TestStruct>().0);
+        let nullable: Nullable<TestStruct> = Nullable::from(thing);
+        assert!(nullable.is_null());
+        assert!(unsafe { nullable.into_box() }.is_none());
+    }
+
+    #[test]
+    fn arc_ptr_is_boxed() {
+        fn returned() -> (ArcPtr<TestStruct>, Nullable<ArcPtr<TestStruct>>) {
+            let arc = ArcPtr::new(TestStruct {
+                field1: 42,
+                field2: "shared".into(),
+            });
+            let nullable = Nullable::from(Some(arc.clone()));
+            (arc, nullable)
+        }
+
+        let (arc, nullable) = returned();
+        assert_eq!(arc.strong_count(), 2);
+
+        let arc_ptr = unsafe { nullable.into_box() }.unwrap();
+        assert_eq!((*arc_ptr).as_ref(), Some(&*arc));
+        drop(arc_ptr);
+        assert_eq!(arc.strong_count(), 1);
     }
 }
@@ -1,18 +1,235 @@
+use alloc::alloc::{alloc, dealloc, handle_alloc_error, Layout};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::sync::atomic::{self, AtomicUsize, Ordering};
 
-use crate::nullable::{null, Nullable};
+use crate::inout::OutPtr;
 
+/// Precedes every value shared by `ArcPtr`, so that a thin pointer to the value is enough to
+/// clone or release it without knowing its type.
+#[repr(C)]
+struct Header {
+    strong: AtomicUsize,
+    /// One more than the number of `WeakPtr`s while any `ArcPtr` remains, as with `Arc`.
+    weak: AtomicUsize,
+    /// The number of elements in a slice, or 1 for any other value.
+    len: usize,
+    vtable: &'static VTable,
+}
+
+/// How to drop and free a value of the type it was created with.
+struct VTable {
+    layout: fn(len: usize) -> Layout,
+    drop_in_place: unsafe fn(value: *mut u8, len: usize),
+}
+
+struct VTables<T>(PhantomData<T>);
+
+impl<T> VTables<T> {
+    const VALUE: &'static VTable = &VTable {
+        layout: value_layout::<T>,
+        drop_in_place: drop_value::<T>,
+    };
+
+    const SLICE: &'static VTable = &VTable {
+        layout: slice_layout::<T>,
+        drop_in_place: drop_slice::<T>,
+    };
+}
+
+fn value_layout<T>(_len: usize) -> Layout {
+    Layout::new::<T>()
+}
+
+unsafe fn drop_value<T>(value: *mut u8, _len: usize) {
+    core::ptr::drop_in_place(value as *mut T)
+}
+
+fn slice_layout<T>(len: usize) -> Layout {
+    Layout::array::<T>(len).expect("slice is too long")
+}
+
+unsafe fn drop_slice<T>(value: *mut u8, len: usize) {
+    core::ptr::drop_in_place(core::ptr::slice_from_raw_parts_mut(value as *mut T, len))
+}
+
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+/// Returns the layout of an allocation for the header and a value with the given layout, and
+/// the offset of the value, which the header immediately precedes.
+fn allocation(value: Layout) -> (Layout, usize) {
+    let header = Layout::new::<Header>();
+    let align = value.align().max(header.align());
+    let offset = header.size().div_ceil(align) * align;
+    let layout = offset
+        .checked_add(value.size())
+        .and_then(|size| Layout::from_size_align(size, align).ok())
+        .expect("value is too large");
+    (layout, offset)
+}
+
+/// Allocates a header with one strong reference, returning a pointer to the uninitialized value.
+unsafe fn allocate(vtable: &'static VTable, len: usize) -> *mut u8 {
+    let (layout, offset) = allocation((vtable.layout)(len));
+    let base = alloc(layout);
+    if base.is_null() {
+        handle_alloc_error(layout);
+    }
+
+    let value = base.add(offset);
+    let header = Header {
+        strong: AtomicUsize::new(1),
+        weak: AtomicUsize::new(1),
+        len,
+        vtable,
+    };
+    core::ptr::write(
+        value.sub(core::mem::size_of::<Header>()) as *mut Header,
+        header,
+    );
+    value
+}
+
+unsafe fn header<'a>(value: *const u8) -> &'a Header {
+    &*(value.sub(core::mem::size_of::<Header>()) as *const Header)
+}
+
+unsafe fn retain(value: *const u8) {
+    let old = header(value).strong.fetch_add(1, Ordering::Relaxed);
+    assert!(old < MAX_REFCOUNT, "too many references");
+}
+
+unsafe fn release(value: *const u8) {
+    let header = header(value);
+    if header.strong.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+    atomic::fence(Ordering::Acquire);
+    (header.vtable.drop_in_place)(value as *mut u8, header.len);
+    release_weak(value);
+}
+
+unsafe fn retain_weak(value: *const u8) {
+    let old = header(value).weak.fetch_add(1, Ordering::Relaxed);
+    assert!(old < MAX_REFCOUNT, "too many references");
+}
+
+unsafe fn release_weak(value: *const u8) {
+    let header = header(value);
+    if header.weak.fetch_sub(1, Ordering::Release) != 1 {
+        return;
+    }
+    atomic::fence(Ordering::Acquire);
+    #[cfg(feature = "leak-tracker")]
+    crate::leaks::untrack(value);
+    let (layout, offset) = allocation((header.vtable.layout)(header.len));
+    dealloc(value.sub(offset) as *mut u8, layout);
+}
+
+/// Takes a strong reference if the value has not yet been dropped.
+unsafe fn upgrade(value: *const u8) -> bool {
+    let strong = &header(value).strong;
+    let mut count = strong.load(Ordering::Relaxed);
+    loop {
+        if count == 0 {
+            return false;
+        }
+        assert!(count < MAX_REFCOUNT, "too many references");
+        match strong.compare_exchange_weak(count, count + 1, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(x) => count = x,
+        }
+    }
+}
+
+unsafe fn strong_count(value: *const u8) -> usize {
+    header(value).strong.load(Ordering::SeqCst)
+}
+
+unsafe fn weak_count(value: *const u8) -> usize {
+    let header = header(value);
+    match header.strong.load(Ordering::SeqCst) {
+        0 => 0,
+        _ => header.weak.load(Ordering::SeqCst) - 1,
+    }
+}
+
+/// An atomically reference counted pointer to a value, which may be shared with C.
+///
+/// Unlike `Arc`, the reference counts are stored with the type's drop function, so any `ArcPtr`
+/// may be cast to `ArcPtr<c_void>` and released with `arc_drop`, including `ArcPtr<dyn Trait>`
+/// and slices.
+///
+/// It may be null when returned over the FFI, in place of a `Nullable`, and dropping a null
+/// `ArcPtr` does nothing.
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct ArcPtr<T: ?Sized>(*const T);
 
+unsafe impl<T: ?Sized + Send + Sync> Send for ArcPtr<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for ArcPtr<T> {}
+
+impl<T> ArcPtr<T> {
+    pub fn new(value: T) -> ArcPtr<T> {
+        unsafe {
+            let ptr = allocate(VTables::<T>::VALUE, 1) as *mut T;
+            core::ptr::write(ptr, value);
+            #[cfg(feature = "leak-tracker")]
+            crate::leaks::track(ptr, "ArcPtr", core::any::type_name::<T>());
+            ArcPtr(ptr)
+        }
+    }
+
+    pub fn null() -> ArcPtr<T> {
+        ArcPtr(core::ptr::null())
+    }
+
+    /// Copies the value into a new `Arc`, as an `Arc` cannot share this allocation.
+    pub fn into_arc(self) -> Arc<T>
+    where
+        T: Clone,
+    {
+        Arc::new((*self).clone())
+    }
+}
+
+impl<T> ArcPtr<[T]> {
+    /// Reclaims a slice previously released with `into_raw` and cast to a pointer to its first
+    /// element, reading its length from the allocation.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `ArcPtr<[T]>::into_raw`, and that reference must not have been
+    /// released already.
+    pub unsafe fn from_raw_thin(ptr: *const T) -> ArcPtr<[T]> {
+        let len = header(ptr as *const u8).len;
+        ArcPtr(core::ptr::slice_from_raw_parts(ptr, len))
+    }
+}
+
+impl ArcPtr<c_void> {
+    /// Reinterprets a type-erased pointer as pointing to a `U`.
+    ///
+    /// # Safety
+    ///
+    /// The value must have been created as a `U`.
+    pub unsafe fn cast<U>(self) -> ArcPtr<U> {
+        ArcPtr(self.into_raw() as *const U)
+    }
+}
+
 impl<T: ?Sized> ArcPtr<T> {
     #[doc(hidden)]
     pub(crate) fn as_ptr(&self) -> *const T {
         self.0
     }
 
+    fn thin(&self) -> *const u8 {
+        self.0 as *const u8
+    }
+
     pub fn is_null(&self) -> bool {
         self.0.is_null()
     }
@@ -25,8 +242,57 @@ impl<T: ?Sized> ArcPtr<T> {
         }
     }
 
-    pub fn into_arc(self) -> Arc<T> {
-        unsafe { Arc::from_raw(self.0 as *mut T) }
+    /// Converts to a pointer to an unsized type, such as a trait object, with `coerce`, which
+    /// must return the pointer it was given, as `|x| x` does.
+    ///
+    /// The coercion is of the raw pointer rather than a reference to the value, so that the
+    /// result may still be used to reach the header before the value.
+    pub fn unsize<U: ?Sized>(self, coerce: fn(*const T) -> *const U) -> ArcPtr<U> {
+        let ptr = coerce(self.0);
+        assert_eq!(
+            ptr as *const u8,
+            self.thin(),
+            "coercion must not change the address"
+        );
+        core::mem::forget(self);
+        ArcPtr(ptr)
+    }
+
+    /// Erases the type, for passing to C. The value is still dropped as a `T`.
+    pub fn erase(self) -> ArcPtr<c_void> {
+        ArcPtr(self.into_raw() as *const c_void)
+    }
+
+    /// Creates a new weak reference to this `ArcPtr`'s value.
+    pub fn downgrade(&self) -> WeakPtr<T> {
+        unsafe { retain_weak(self.thin()) };
+        WeakPtr(self.0)
+    }
+
+    pub fn strong_count(&self) -> usize {
+        unsafe { strong_count(self.thin()) }
+    }
+
+    pub fn weak_count(&self) -> usize {
+        unsafe { weak_count(self.thin()) }
+    }
+
+    /// Releases the strong reference held by this `ArcPtr` to the caller.
+    pub fn into_raw(self) -> *const T {
+        let ptr = self.0;
+        core::mem::forget(self);
+        ptr
+    }
+
+    /// Reclaims a strong reference previously released with `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or come from `ArcPtr::into_raw` for a `T`, and that reference must not
+    /// have been released already. A pointer from `Arc::into_raw` is not enough, as it is not
+    /// preceded by the header that `ArcPtr` needs.
+    pub unsafe fn from_raw(ptr: *const T) -> ArcPtr<T> {
+        ArcPtr(ptr)
     }
 }
 
@@ -40,50 +306,382 @@ impl<T: ?Sized> core::ops::Deref for ArcPtr<T> {
 
 impl<T: ?Sized> Clone for ArcPtr<T> {
     fn clone(&self) -> ArcPtr<T> {
-        let arc = unsafe { Arc::from_raw(self.0) };
-        let arc1 = Arc::clone(&arc);
-        core::mem::forget(arc);
-        ArcPtr(Arc::into_raw(arc1))
+        unsafe { retain(self.thin()) };
+        ArcPtr(self.0)
     }
 }
 
 impl<T: ?Sized> Drop for ArcPtr<T> {
     fn drop(&mut self) {
-        unsafe { Arc::from_raw(self.0 as *mut c_void) };
+        if !self.is_null() {
+            unsafe { release(self.thin()) };
+        }
+    }
+}
+
+impl<T> From<T> for ArcPtr<T> {
+    fn from(item: T) -> ArcPtr<T> {
+        ArcPtr::new(item)
     }
 }
 
-impl<T: ?Sized> From<Arc<T>> for ArcPtr<T> {
+/// Moves the value into a new `ArcPtr`, cloning it if the `Arc` is still shared, as an `Arc`'s
+/// own allocation has no room for the header that C needs to release it.
+impl<T: Clone> From<Arc<T>> for ArcPtr<T> {
     fn from(arc: Arc<T>) -> ArcPtr<T> {
-        ArcPtr(Arc::into_raw(arc))
+        ArcPtr::new(Arc::unwrap_or_clone(arc))
     }
 }
 
-impl<T> From<T> for ArcPtr<T> {
-    fn from(item: T) -> ArcPtr<T> {
-        ArcPtr(Arc::into_raw(Arc::new(item)))
+impl<T> From<Vec<T>> for ArcPtr<[T]> {
+    fn from(mut vec: Vec<T>) -> ArcPtr<[T]> {
+        let len = vec.len();
+        unsafe {
+            let ptr = allocate(VTables::<T>::SLICE, len) as *mut T;
+            core::ptr::copy_nonoverlapping(vec.as_ptr(), ptr, len);
+            vec.set_len(0);
+            #[cfg(feature = "leak-tracker")]
+            crate::leaks::track(ptr, "ArcPtr", core::any::type_name::<[T]>());
+            ArcPtr(core::ptr::slice_from_raw_parts(ptr, len))
+        }
+    }
+}
+
+impl From<&str> for ArcPtr<str> {
+    fn from(string: &str) -> ArcPtr<str> {
+        let bytes = ArcPtr::<[u8]>::from(string.as_bytes().to_vec());
+        ArcPtr(bytes.into_raw() as *const str)
+    }
+}
+
+pub fn nullable_arc<T>(thing: T) -> ArcPtr<T> {
+    ArcPtr::from(thing)
+}
+
+/// A weak reference to a value shared by `ArcPtr`s, which does not keep the value alive.
+///
+/// Like `ArcPtr<T>`, it is a pointer to the value, but it must only be dereferenced after
+/// upgrading it to an `ArcPtr`.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct WeakPtr<T: ?Sized>(*const T);
+
+unsafe impl<T: ?Sized + Send + Sync> Send for WeakPtr<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for WeakPtr<T> {}
+
+impl<T> WeakPtr<T> {
+    pub fn null() -> WeakPtr<T> {
+        WeakPtr(core::ptr::null())
     }
 }
 
-pub fn nullable_arc<T>(thing: T) -> Nullable<ArcPtr<T>> {
-    Nullable::from(ArcPtr::from(thing))
+impl<T: ?Sized> WeakPtr<T> {
+    fn thin(&self) -> *const u8 {
+        self.0 as *const u8
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+
+    /// Returns a strong reference, or `None` if the value has already been dropped.
+    pub fn upgrade(&self) -> Option<ArcPtr<T>> {
+        match unsafe { upgrade(self.thin()) } {
+            true => Some(ArcPtr(self.0)),
+            false => None,
+        }
+    }
+
+    pub fn strong_count(&self) -> usize {
+        unsafe { strong_count(self.thin()) }
+    }
+
+    pub fn weak_count(&self) -> usize {
+        unsafe { weak_count(self.thin()) }
+    }
+
+    /// Releases the weak reference held by this `WeakPtr` to the caller.
+    pub fn into_raw(self) -> *const T {
+        let ptr = self.0;
+        core::mem::forget(self);
+        ptr
+    }
+
+    /// Reclaims a weak reference previously released with `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or come from `WeakPtr::into_raw` for a `T`, and that weak reference
+    /// must not have been released already.
+    pub unsafe fn from_raw(ptr: *const T) -> WeakPtr<T> {
+        WeakPtr(ptr)
+    }
+}
+
+impl<T: ?Sized> Clone for WeakPtr<T> {
+    fn clone(&self) -> WeakPtr<T> {
+        unsafe { retain_weak(self.thin()) };
+        WeakPtr(self.0)
+    }
+}
+
+impl<T: ?Sized> Drop for WeakPtr<T> {
+    fn drop(&mut self) {
+        if !self.is_null() {
+            unsafe { release_weak(self.thin()) };
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn arc_clone(arc: ArcPtr<c_void>) -> Nullable<ArcPtr<c_void>> {
+pub extern "C" fn arc_clone(arc: ArcPtr<c_void>) -> ArcPtr<c_void> {
+    ffi_boundary!(&OutPtr::null(), ArcPtr::null(), {
+        // The caller keeps its own reference, so it must not be released here.
+        let arc = core::mem::ManuallyDrop::new(arc);
+        match arc.is_null() {
+            true => ArcPtr::null(),
+            false => (*arc).clone(),
+        }
+    })
+}
+
+/// Releases one reference to any `ArcPtr`, dropping its value as the type it was created with if
+/// it was the last.
+#[no_mangle]
+pub extern "C" fn arc_drop(arc: ArcPtr<c_void>) -> bool {
+    ffi_boundary!(&OutPtr::null(), false, {
+        match arc.is_null() {
+            true => false,
+            false => {
+                drop(arc);
+                true
+            }
+        }
+    })
+}
+
+/// Returns the number of strong references to the value, or 0 if `arc` is null.
+#[no_mangle]
+pub extern "C" fn arc_strong_count(arc: ArcPtr<c_void>) -> usize {
+    let arc = core::mem::ManuallyDrop::new(arc);
     match arc.is_null() {
-        true => null(),
-        false => Nullable::from(arc.clone()),
+        true => 0,
+        false => arc.strong_count(),
     }
 }
 
+/// Returns the number of weak references to the value, or 0 if `arc` is null.
 #[no_mangle]
-pub extern "C" fn arc_drop(arc: ArcPtr<c_void>) -> bool {
+pub extern "C" fn arc_weak_count(arc: ArcPtr<c_void>) -> usize {
+    let arc = core::mem::ManuallyDrop::new(arc);
     match arc.is_null() {
-        true => false,
-        false => {
-            arc.into_arc();
-            true
+        true => 0,
+        false => arc.weak_count(),
+    }
+}
+
+/// Creates a weak reference to the value, which must be released with `weak_drop`.
+#[no_mangle]
+pub extern "C" fn arc_downgrade(arc: ArcPtr<c_void>) -> WeakPtr<c_void> {
+    ffi_boundary!(&OutPtr::null(), WeakPtr::null(), {
+        let arc = core::mem::ManuallyDrop::new(arc);
+        match arc.is_null() {
+            true => WeakPtr::null(),
+            false => arc.downgrade(),
+        }
+    })
+}
+
+/// Returns a new strong reference to the value, or null if it has already been dropped.
+#[no_mangle]
+pub extern "C" fn weak_upgrade(weak: WeakPtr<c_void>) -> ArcPtr<c_void> {
+    ffi_boundary!(&OutPtr::null(), ArcPtr::null(), {
+        let weak = core::mem::ManuallyDrop::new(weak);
+        match weak.is_null() {
+            true => ArcPtr::null(),
+            false => weak.upgrade().unwrap_or_else(ArcPtr::null),
         }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn weak_clone(weak: WeakPtr<c_void>) -> WeakPtr<c_void> {
+    ffi_boundary!(&OutPtr::null(), WeakPtr::null(), {
+        let weak = core::mem::ManuallyDrop::new(weak);
+        match weak.is_null() {
+            true => WeakPtr::null(),
+            false => (*weak).clone(),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn weak_drop(weak: WeakPtr<c_void>) -> bool {
+    ffi_boundary!(&OutPtr::null(), false, {
+        match weak.is_null() {
+            true => false,
+            false => {
+                drop(weak);
+                true
+            }
+        }
+    })
+}
+
+/// Returns the number of strong references to the value, or 0 if it has been dropped or `weak`
+/// is null.
+#[no_mangle]
+pub extern "C" fn weak_strong_count(weak: WeakPtr<c_void>) -> usize {
+    let weak = core::mem::ManuallyDrop::new(weak);
+    match weak.is_null() {
+        true => 0,
+        false => weak.strong_count(),
+    }
+}
+
+/// Returns the number of weak references to the value, or 0 if it has been dropped or `weak` is
+/// null.
+#[no_mangle]
+pub extern "C" fn weak_weak_count(weak: WeakPtr<c_void>) -> usize {
+    let weak = core::mem::ManuallyDrop::new(weak);
+    match weak.is_null() {
+        true => 0,
+        false => weak.weak_count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    /// Counts its drops.
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    trait Named {
+        fn name(&self) -> &str;
+    }
+
+    impl Named for Counted {
+        fn name(&self) -> &str {
+            "counted"
+        }
+    }
+
+    #[repr(align(64))]
+    struct Aligned {
+        _counted: Counted,
+    }
+
+    fn drops() -> (Arc<AtomicUsize>, impl Fn() -> Counted) {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let inner = Arc::clone(&drops);
+        (drops, move || Counted(Arc::clone(&inner)))
+    }
+
+    #[test]
+    fn erased_drop() {
+        let (drops, counted) = drops();
+
+        let arc = ArcPtr::new(counted()).erase();
+        let cloned = arc_clone(unsafe { ArcPtr::from_raw(arc.as_ptr()) });
+        assert!(arc_drop(arc));
+        assert_eq!(drops.load(Ordering::SeqCst), 0);
+        assert!(arc_drop(cloned));
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+
+        let aligned = ArcPtr::new(Aligned {
+            _counted: counted(),
+        });
+        assert_eq!(aligned.as_ptr() as usize % 64, 0);
+        assert!(arc_drop(aligned.erase()));
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+
+        let slice = ArcPtr::<[Counted]>::from(alloc::vec![counted(), counted(), counted()]);
+        let thin = slice.into_raw() as *const Counted;
+        let slice = unsafe { ArcPtr::<[Counted]>::from_raw_thin(thin) };
+        assert_eq!(slice.len(), 3);
+        assert!(arc_drop(slice.erase()));
+        assert_eq!(drops.load(Ordering::SeqCst), 5);
+
+        assert!(!arc_drop(unsafe { ArcPtr::from_raw(core::ptr::null()) }));
+    }
+
+    #[test]
+    fn trait_objects() {
+        let (drops, counted) = drops();
+
+        let named: ArcPtr<dyn Named> = ArcPtr::new(counted()).unsize(|x| x);
+        assert_eq!(named.name(), "counted");
+        let weak = named.downgrade();
+
+        let erased = named.clone().erase();
+        drop(named);
+        assert_eq!(weak.strong_count(), 1);
+        assert!(arc_drop(erased));
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn std_arcs() {
+        let arc = Arc::new(42u64);
+        let shared = Arc::clone(&arc);
+        let ptr: ArcPtr<u64> = ArcPtr::from(arc);
+        assert_eq!(*ptr, 42);
+        assert_eq!(Arc::strong_count(&shared), 1);
+
+        let arc = ptr.clone().into_arc();
+        assert_eq!(*arc, 42);
+        assert_eq!(ptr.strong_count(), 1);
+    }
+
+    #[test]
+    fn weak_ptr() {
+        let arc = ArcPtr::from(42u64);
+        let weak = arc.downgrade();
+        assert_eq!((arc.strong_count(), arc.weak_count()), (1, 1));
+
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(*upgraded, 42);
+        assert_eq!(weak.clone().strong_count(), 2);
+        assert_eq!(weak.weak_count(), 1);
+
+        drop((arc, upgraded));
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+    }
+
+    #[test]
+    fn weak_ffi() {
+        let arc = ArcPtr::from(42u64);
+        let raw = || unsafe { ArcPtr::from_raw(arc.as_ptr() as *const c_void) };
+
+        let weak = arc_downgrade(raw()).into_raw();
+        let weak = || unsafe { WeakPtr::from_raw(weak) };
+        let clone = weak_clone(weak()).into_raw();
+        assert_eq!(arc_strong_count(raw()), 1);
+        assert_eq!(arc_weak_count(raw()), 2);
+
+        let upgraded = weak_upgrade(weak());
+        assert!(!upgraded.is_null());
+        assert_eq!(upgraded.as_ptr(), arc.as_ptr() as *const c_void);
+        assert_eq!(weak_strong_count(weak()), 2);
+        drop(upgraded);
+
+        assert!(weak_drop(unsafe { WeakPtr::from_raw(clone) }));
+        assert_eq!(weak_weak_count(weak()), 1);
+
+        drop(arc);
+        assert!(weak_upgrade(weak()).is_null());
+        assert_eq!(weak_strong_count(weak()), 0);
+        assert!(weak_drop(weak()));
+        assert!(!weak_drop(unsafe { WeakPtr::from_raw(core::ptr::null()) }));
     }
 }
@@ -0,0 +1,39 @@
+/// A stable identifier for a type, for foreign code to tell cursed which type a generic
+/// container holds.
+///
+/// Unlike `core::any::TypeId`, the value is the same across compilations: it is the 64-bit
+/// FNV-1a hash of the name the type was declared with, such as the constant names given to
+/// `generate_vec_ffi!`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TypeTag {
+    pub id: u64,
+}
+
+impl TypeTag {
+    pub const fn from_name(name: &str) -> TypeTag {
+        let bytes = name.as_bytes();
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut i = 0;
+        while i < bytes.len() {
+            hash ^= bytes[i] as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            i += 1;
+        }
+        TypeTag { id: hash }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable() {
+        const TAG: TypeTag = TypeTag::from_name("TYPE_U64");
+        assert_eq!(TypeTag::from_name("").id, 0xcbf2_9ce4_8422_2325);
+        assert_eq!(TypeTag::from_name("a").id, 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(TAG, TypeTag::from_name("TYPE_U64"));
+        assert_ne!(TAG, TypeTag::from_name("TYPE_STRING"));
+    }
+}
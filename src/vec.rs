@@ -15,16 +15,47 @@ use crate::{
 };
 
 pub mod ffi;
+pub mod frozen;
+pub mod primitive;
+
+pub use frozen::FrozenVec;
+pub use primitive::PrimitiveVec;
 
 pub type RawValue = dyn Any + 'static + Send + Sync;
 
+/// An index was past the end of a vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub index: usize,
+    pub len: usize,
+}
+
+impl core::fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "index {} is out of bounds for a vector of length {}",
+            self.index, self.len
+        )
+    }
+}
+
+#[inline]
+fn check_index(index: usize, len: usize) -> Result<(), OutOfBounds> {
+    match index < len {
+        true => Ok(()),
+        false => Err(OutOfBounds { index, len }),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TaggedAny(TypeId, Arc<RawValue>);
 unsafe impl Send for TaggedAny {}
 unsafe impl Sync for TaggedAny {}
 
 impl TaggedAny {
-    fn resolve<T: 'static + Send + Sync>(&self) -> Result<Arc<T>, TaggedAny> {
+    /// Returns the value as a `T`, or itself if it is of another type.
+    pub fn resolve<T: 'static + Send + Sync>(&self) -> Result<Arc<T>, TaggedAny> {
         let x = self.clone();
         match x.1.downcast() {
             Ok(v) => Ok(v),
@@ -67,14 +98,55 @@ impl AnyVec {
         let typed_void = guard.get(index)?;
         Some(typed_void.resolve())
     }
+
+    /// Iterates over a snapshot of the items, so later changes are not seen.
+    pub fn iter(&self) -> AnyIter {
+        AnyIter(self.0.read().clone().into_iter())
+    }
+}
+
+impl IntoIterator for AnyVec {
+    type Item = TaggedAny;
+    type IntoIter = AnyIter;
+
+    fn into_iter(self) -> AnyIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for &AnyVec {
+    type Item = TaggedAny;
+    type IntoIter = AnyIter;
+
+    fn into_iter(self) -> AnyIter {
+        self.iter()
+    }
+}
+
+/// An iterator over a snapshot of an `AnyVec`.
+#[derive(Debug)]
+pub struct AnyIter(vec::IntoIter<TaggedAny>);
+
+impl Iterator for AnyIter {
+    type Item = TaggedAny;
+
+    fn next(&mut self) -> Option<TaggedAny> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RawVec {
     vec: Arc<RwLock<RealVec<Arc<RawValue>>>>,
-
-    #[cfg(debug_assertions)]
+    /// Checked by exports that add items, as foreign code may pass any vector.
     ty: TypeId,
+    /// Reported by the leak tracker, as exports such as `vec_share` do not know the type.
+    #[cfg(feature = "leak-tracker")]
+    type_name: &'static str,
 }
 
 impl RawVec {
@@ -82,12 +154,38 @@ impl RawVec {
     fn new<T: 'static>() -> RawVec {
         RawVec {
             vec: Arc::new(RwLock::new(RealVec::new())),
-
-            #[cfg(debug_assertions)]
             ty: TypeId::of::<T>(),
+            #[cfg(feature = "leak-tracker")]
+            type_name: core::any::type_name::<T>(),
         }
     }
 
+    /// Boxes the handle to pass to C, recording it with the leak tracker.
+    pub(crate) fn into_raw(self) -> *const RawVec {
+        #[cfg(feature = "leak-tracker")]
+        let type_name = self.type_name;
+        let ptr = Box::into_raw(Box::new(self)) as *const RawVec;
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "Vec", type_name);
+        ptr
+    }
+
+    /// Reclaims a handle boxed by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` and must not have been reclaimed already.
+    pub(crate) unsafe fn from_raw(ptr: *const RawVec) -> RawVec {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr);
+        *Box::from_raw(ptr as *mut RawVec)
+    }
+
+    #[inline]
+    fn holds<T: 'static>(&self) -> bool {
+        self.ty == TypeId::of::<T>()
+    }
+
     #[inline]
     fn iter<F, O>(&self, f: F) -> O
     where
@@ -117,12 +215,128 @@ impl RawVec {
         let item = guard.get(index)?;
         Some(Arc::clone(&item))
     }
+
+    /// Inserts at `index`, which may be the length to append.
+    #[inline]
+    fn insert(&mut self, index: usize, item: Arc<RawValue>) -> Result<(), OutOfBounds> {
+        let mut guard = self.vec.write();
+        let len = guard.len();
+        if index > len {
+            return Err(OutOfBounds { index, len });
+        }
+        guard.insert(index, item);
+        Ok(())
+    }
+
+    #[inline]
+    fn remove(&mut self, index: usize) -> Result<Arc<RawValue>, OutOfBounds> {
+        let mut guard = self.vec.write();
+        check_index(index, guard.len())?;
+        Ok(guard.remove(index))
+    }
+
+    #[inline]
+    fn swap_remove(&mut self, index: usize) -> Result<Arc<RawValue>, OutOfBounds> {
+        let mut guard = self.vec.write();
+        check_index(index, guard.len())?;
+        Ok(guard.swap_remove(index))
+    }
+
+    /// Replaces the item at `index`, returning the old one.
+    #[inline]
+    fn set(&mut self, index: usize, item: Arc<RawValue>) -> Result<Arc<RawValue>, OutOfBounds> {
+        let mut guard = self.vec.write();
+        check_index(index, guard.len())?;
+        Ok(core::mem::replace(&mut guard[index], item))
+    }
+
+    #[inline]
+    fn clear(&mut self) {
+        self.vec.write().clear();
+    }
+
+    #[inline]
+    fn truncate(&mut self, len: usize) {
+        self.vec.write().truncate(len);
+    }
+
+    #[inline]
+    fn reserve(&mut self, additional: usize) {
+        self.vec.write().reserve(additional);
+    }
+
+    /// Copies the items under a single read lock.
+    #[inline]
+    fn snapshot(&self) -> RawIter {
+        RawIter(self.vec.read().clone().into_iter())
+    }
+
+    /// Copies the list into a new lock, so that neither copy sees changes to the other.
+    #[inline]
+    fn deep_clone(&self) -> RawVec {
+        RawVec {
+            vec: Arc::new(RwLock::new(self.vec.read().clone())),
+            ty: self.ty,
+            #[cfg(feature = "leak-tracker")]
+            type_name: self.type_name,
+        }
+    }
+
+    /// Appends the items of `other`, which may share this vector.
+    #[inline]
+    fn extend_from(&mut self, other: &RawVec) {
+        // Copied first, as `other` may be this vector and the lock is not reentrant.
+        self.extend(other.snapshot());
+    }
+
+    #[inline]
+    fn extend<I: IntoIterator<Item = Arc<RawValue>>>(&mut self, items: I) {
+        self.vec.write().extend(items);
+    }
+}
+
+/// An iterator over a snapshot of a `RawVec`, exported as `vec_iter_new`.
+#[derive(Debug)]
+pub struct RawIter(vec::IntoIter<Arc<RawValue>>);
+
+impl Iterator for RawIter {
+    type Item = Arc<RawValue>;
+
+    fn next(&mut self) -> Option<Arc<RawValue>> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
 #[repr(transparent)]
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Vec<T>(RawVec, PhantomData<T>);
 
+// Derived `Clone` would needlessly require `T: Clone`, as only the handle is cloned.
+impl<T> Clone for Vec<T> {
+    /// The same as `share`. Prefer calling `share` or `deep_clone`, to make it clear which is
+    /// meant.
+    fn clone(&self) -> Vec<T> {
+        self.share()
+    }
+}
+
+impl<T> Vec<T> {
+    /// Returns another handle to the same list, so that changes through either are seen by both.
+    pub fn share(&self) -> Vec<T> {
+        Vec(self.0.clone(), PhantomData)
+    }
+
+    /// Returns a new list with the same items, so that changes to either are not seen by the
+    /// other. The items are immutable, so they are shared rather than cloned.
+    pub fn deep_clone(&self) -> Vec<T> {
+        Vec(self.0.deep_clone(), PhantomData)
+    }
+}
+
 impl<T: Send + Sync + 'static> Vec<T> {
     pub fn new() -> Vec<T> {
         Vec(RawVec::new::<T>(), PhantomData)
@@ -144,17 +358,73 @@ impl<T: Send + Sync + 'static> Vec<T> {
         self.0.get(index).and_then(|v| v.downcast().ok())
     }
 
+    /// Inserts `item` at `index`, shifting later items along. `index` may be the length.
+    pub fn insert(&mut self, index: usize, item: T) -> Result<(), OutOfBounds> {
+        self.0.insert(index, Arc::new(item))
+    }
+
+    /// Removes the item at `index`, shifting later items back. The item is `None` if it was
+    /// pushed over the FFI as another type.
+    pub fn remove(&mut self, index: usize) -> Result<Option<Arc<T>>, OutOfBounds> {
+        self.0.remove(index).map(|v| v.downcast().ok())
+    }
+
+    /// Removes the item at `index`, replacing it with the last item.
+    pub fn swap_remove(&mut self, index: usize) -> Result<Option<Arc<T>>, OutOfBounds> {
+        self.0.swap_remove(index).map(|v| v.downcast().ok())
+    }
+
+    /// Replaces the item at `index`, returning the old one.
+    pub fn set(&mut self, index: usize, item: T) -> Result<Option<Arc<T>>, OutOfBounds> {
+        self.0.set(index, Arc::new(item)).map(|v| v.downcast().ok())
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.0.reserve(additional);
+    }
+
+    /// Iterates over a snapshot of the items, so later changes are not seen. Items pushed over the
+    /// FFI as another type are skipped.
+    pub fn iter(&self) -> Iter<T> {
+        Iter(self.0.snapshot(), PhantomData)
+    }
+
+    /// Returns an immutable copy of the list, which can be read without locking.
+    pub fn snapshot(&self) -> FrozenVec<T> {
+        FrozenVec::from_raw_vec(&self.0)
+    }
+
+    /// Appends the items of `other`, sharing them rather than cloning them.
+    pub fn extend_from(&mut self, other: &Vec<T>) {
+        self.0.extend_from(&other.0);
+    }
+
     pub fn into_raw(self) -> *const Vec<T> {
-        Box::into_raw(Box::new(self.0)) as *const _
+        self.0.into_raw() as *const Vec<T>
     }
 
+    /// Reclaims a vector previously returned by `into_raw`, or a `RawVec` from an export such as
+    /// `vec_share`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live vector of `T`s from `into_raw` or an export, which must not be
+    /// used again once reclaimed.
     pub unsafe fn from_raw(ptr: *const Vec<T>) -> Vec<T> {
-        let raw_vec: Box<RawVec> = Box::from_raw(ptr as *mut _);
+        let raw_vec = RawVec::from_raw(ptr as *const RawVec);
 
         #[cfg(debug_assertions)]
         assert_eq!(raw_vec.ty, TypeId::of::<T>());
 
-        Vec(*raw_vec, PhantomData)
+        Vec(raw_vec, PhantomData)
     }
 
     pub fn to_vec(&self) -> Option<RealVec<Arc<T>>> {
@@ -178,6 +448,36 @@ impl<T: Send + Sync + 'static> Vec<T> {
     }
 }
 
+impl<T: Send + Sync + 'static> IntoIterator for Vec<T> {
+    type Item = Arc<T>;
+    type IntoIter = Iter<T>;
+
+    fn into_iter(self) -> Iter<T> {
+        self.iter()
+    }
+}
+
+impl<T: Send + Sync + 'static> IntoIterator for &Vec<T> {
+    type Item = Arc<T>;
+    type IntoIter = Iter<T>;
+
+    fn into_iter(self) -> Iter<T> {
+        self.iter()
+    }
+}
+
+/// An iterator over a snapshot of a `Vec<T>`.
+#[derive(Debug)]
+pub struct Iter<T>(RawIter, PhantomData<T>);
+
+impl<T: Send + Sync + 'static> Iterator for Iter<T> {
+    type Item = Arc<T>;
+
+    fn next(&mut self) -> Option<Arc<T>> {
+        self.0.by_ref().find_map(|v| v.downcast().ok())
+    }
+}
+
 impl<T: Send + Sync + 'static> From<RealVec<T>> for Vec<T> {
     fn from(vec: RealVec<T>) -> Vec<T> {
         let mut out = Vec::new();
@@ -249,6 +549,129 @@ mod tests {
         assert_eq!(vec.get(1), Some(Arc::new(42usize)));
     }
 
+    #[test]
+    fn mutation() {
+        let mut vec = Vec::from(vec![1u32, 2, 3]);
+        vec.insert(3, 4).unwrap();
+        vec.insert(0, 0).unwrap();
+        assert_eq!(vec.insert(6, 6), Err(OutOfBounds { index: 6, len: 5 }));
+        assert_eq!(vec.remove(1), Ok(Some(Arc::new(1))));
+        assert_eq!(vec.swap_remove(0), Ok(Some(Arc::new(0))));
+        assert_eq!(vec.set(0, 5), Ok(Some(Arc::new(4))));
+        assert_eq!(vec.remove(3), Err(OutOfBounds { index: 3, len: 3 }));
+        assert_eq!(vec.to_owned_vec(), [5, 2, 3]);
+
+        let other = vec.share();
+        vec.extend_from(&other);
+        assert_eq!(vec.to_owned_vec(), [5, 2, 3, 5, 2, 3]);
+        vec.truncate(2);
+        vec.reserve(10);
+        assert_eq!(vec.to_owned_vec(), [5, 2]);
+        vec.clear();
+        assert_eq!(vec.len(), 0);
+    }
+
+    #[test]
+    fn ffi_mutation() {
+        use crate::exception::ExceptionKind;
+
+        let vec = Vec::from(vec![1u64, 2, 3]);
+        let raw_vec = vec.into_raw() as *mut RawVec;
+        let handle = || InOut::from(unsafe { &mut *raw_vec });
+        let mut exception = core::ptr::null_mut();
+
+        let value = unsafe { ffi::vec_remove(handle(), 0, OutPtr::null()).into_box() }.unwrap();
+        assert_eq!((**value).downcast_ref::<u64>(), Some(&1));
+        ffi::vec_value_free(Nullable::from(value));
+
+        assert!(ffi::vec_swap_remove(handle(), 2, OutPtr::from(&mut exception)).is_null());
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::OutOfBounds);
+        assert_eq!(
+            e.message(),
+            "index 2 is out of bounds for a vector of length 2"
+        );
+
+        let other = Vec::from(vec![4u64]).into_raw();
+        let other_handle = In::from(unsafe { &*(other as *const RawVec) });
+        ffi::vec_extend_from(handle(), other_handle, OutPtr::null());
+        unsafe { Vec::<u64>::from_raw(other) };
+        ffi::vec_truncate(handle(), 2, OutPtr::null());
+        ffi::vec_reserve(handle(), 4, OutPtr::null());
+        let vec = unsafe { Vec::<u64>::from_raw(raw_vec as *const _) };
+        assert_eq!(vec.to_owned_vec(), [2, 3]);
+
+        let raw_vec = vec.into_raw() as *mut RawVec;
+        let handle = || InOut::from(unsafe { &mut *raw_vec });
+        ffi::vec_clear(handle(), OutPtr::null());
+        assert_eq!(unsafe { &*raw_vec }.len(), 0);
+        unsafe { Vec::<u64>::from_raw(raw_vec as *const _) };
+    }
+
+    #[test]
+    fn share_and_clone() {
+        let mut vec = Vec::from(vec![1u8]);
+        let mut shared = vec.share();
+        let mut cloned = vec.deep_clone();
+        let frozen = vec.snapshot();
+
+        shared.push(2);
+        cloned.push(3);
+        assert_eq!(vec.to_owned_vec(), [1, 2]);
+        assert_eq!(cloned.to_owned_vec(), [1, 3]);
+        assert_eq!(frozen.len(), 1);
+
+        vec.clear();
+        assert_eq!(shared.len(), 0);
+        assert_eq!(frozen.get(0), Some(Arc::new(1)));
+        assert_eq!(frozen.thaw().to_owned_vec(), [1]);
+    }
+
+    #[test]
+    fn iterators() {
+        let mut vec = Vec::from(vec![1u32, 2]);
+        let iter = vec.iter();
+        vec.push(3);
+        assert_eq!(iter.map(|x| *x).collect::<RealVec<_>>(), [1, 2]);
+        assert_eq!((&vec).into_iter().count(), 3);
+        assert_eq!(vec.into_iter().map(|x| *x).sum::<u32>(), 6);
+
+        let mut any = AnyVec::new();
+        any.push(1u8);
+        any.push(String::from("two"));
+        let mut iter = any.into_iter();
+        assert_eq!(*iter.next().unwrap().resolve::<u8>().unwrap(), 1);
+        assert!(iter.next().unwrap().resolve::<u8>().is_err());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn ffi_iter() {
+        let vec = Vec::from(vec![1u64, 2]);
+        let raw_vec = vec.into_raw();
+        let handle = || In::from(unsafe { &*(raw_vec as *const RawVec) });
+
+        let mut iter = unsafe { ffi::vec_iter_new(handle(), OutPtr::null()).into_box() }.unwrap();
+        unsafe { &mut *(raw_vec as *mut Vec<u64>) }.clear();
+
+        let mut values = RealVec::new();
+        loop {
+            let value = ffi::vec_iter_next(InOut::from(&mut *iter), OutPtr::null());
+            match unsafe { value.into_box() } {
+                Some(value) => values.push(*(**value).downcast_ref::<u64>().unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(values, [1, 2]);
+        ffi::vec_iter_free(Nullable::from(iter));
+
+        let mut exception = core::ptr::null_mut();
+        assert!(ffi::vec_iter_new(In::null(), OutPtr::from(&mut exception)).is_null());
+        assert!(!exception.is_null());
+        crate::exception::cursed_exception_free(exception);
+        unsafe { Vec::<u64>::from_raw(raw_vec) };
+    }
+
     #[test]
     fn ffi_life() {
         let mut vec = Vec::new();
@@ -259,6 +682,126 @@ mod tests {
         unsafe { Vec::<usize>::from_raw(raw_vec) };
     }
 
+    #[test]
+    fn ffi_values_outlive_return() {
+        let mut vec = Vec::new();
+        vec.push(42usize);
+        let raw_vec = vec.into_raw();
+
+        let mut exception = core::ptr::null_mut();
+        let handle = In::from(unsafe { &*(raw_vec as *const RawVec) });
+        let value = ffi::vec_get(handle, 0, OutPtr::from(&mut exception));
+        assert!(exception.is_null());
+
+        let value = unsafe { value.into_box() }.unwrap();
+        assert_eq!((**value).downcast_ref::<usize>(), Some(&42usize));
+        ffi::vec_value_free(Nullable::from(value));
+
+        unsafe { Vec::<usize>::from_raw(raw_vec) };
+    }
+
+    #[cfg(not(feature = "demo"))]
+    mod generated {
+        crate::generate_vec_ffi! {
+            TEST_U64 => u64,
+            TEST_STRING => alloc::string::String
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "demo"))]
+    fn ffi_type_tags() {
+        use crate::tag::TypeTag;
+        use generated::*;
+
+        assert_eq!(TEST_U64, TypeTag::from_name("TEST_U64"));
+
+        let mut exception = core::ptr::null_mut();
+        let handle = vec_new(TEST_STRING).into_raw() as *mut RawVec;
+        vec_debug_print(handle, TEST_STRING);
+        vec_free(handle, TEST_STRING, OutPtr::from(&mut exception));
+        assert!(exception.is_null());
+
+        assert!(vec_new(TypeTag::from_name("TEST_NOPE")).is_null());
+        let handle = vec_new(TEST_U64).into_raw() as *mut RawVec;
+        vec_free(
+            handle,
+            TypeTag::from_name("TEST_NOPE"),
+            OutPtr::from(&mut exception),
+        );
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), crate::exception::ExceptionKind::TypeMismatch);
+        vec_free(handle, TEST_U64, OutPtr::null());
+    }
+
+    #[test]
+    #[cfg(not(feature = "demo"))]
+    fn ffi_copies_values() {
+        use crate::exception::ExceptionKind;
+        use generated::*;
+
+        let raw_vec = Vec::from(vec![String::from("b")]).into_raw() as *mut RawVec;
+        let handle = || InOut::from(unsafe { &mut *raw_vec });
+        let raw = |value: &String| In::from(value).cast::<core::ffi::c_void>();
+        let mut exception = core::ptr::null_mut();
+
+        // The values are copied, so they need not outlive the calls.
+        let (a, c) = (String::from("a"), String::from("c"));
+        vec_insert(handle(), TEST_STRING, 0, raw(&a), OutPtr::null());
+        vec_insert(handle(), TEST_STRING, 2, raw(&c), OutPtr::null());
+        vec_set(handle(), TEST_STRING, 1, raw(&c), OutPtr::null());
+        drop((a, c));
+
+        vec_insert(
+            handle(),
+            TEST_U64,
+            0,
+            raw(&String::new()),
+            OutPtr::from(&mut exception),
+        );
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::TypeMismatch);
+        vec_set(
+            handle(),
+            TEST_STRING,
+            3,
+            raw(&String::new()),
+            OutPtr::from(&mut exception),
+        );
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::OutOfBounds);
+        vec_set(
+            handle(),
+            TEST_STRING,
+            0,
+            InRaw::null(),
+            OutPtr::from(&mut exception),
+        );
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::NullArgument);
+
+        // Extending a vector with itself copies its items first.
+        let same = handle();
+        let other = same.to_in();
+        ffi::vec_extend_from(same, other, OutPtr::null());
+
+        let vec = unsafe { Vec::<String>::from_raw(raw_vec as *const _) };
+        assert_eq!(vec.to_owned_vec(), ["a", "c", "c", "a", "c", "c"]);
+        assert_eq!(vec.iter().count(), 6);
+
+        let mut vec = vec;
+        let other = Vec::from(vec![1u64]);
+        let other_handle = In::from(&other.0);
+        ffi::vec_extend_from(
+            InOut::from(&mut vec.0),
+            other_handle,
+            OutPtr::from(&mut exception),
+        );
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::TypeMismatch);
+        assert_eq!(vec.len(), 6);
+    }
+
     #[test]
     #[should_panic]
     #[cfg(debug_assertions)]
@@ -1,44 +1,34 @@
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 
-use super::RawValue;
+use super::{OutOfBounds, RawIter, RawValue};
 use crate::{
-    exception::Exception,
+    exception::{throw, throw_kind, throw_null, Exception, ExceptionKind},
     inout::{In, InOut, InRaw, OutPtr},
     nullable::Nullable,
-    sync::ArcPtr,
     vec::RawVec,
 };
 
-cfg_if::cfg_if! {
-    if #[cfg(feature = "no-std")] {
-        use alloc::format;
-    } else {
-        use std::format;
-    }
-}
-
 #[no_mangle]
 pub extern "C" fn vec_len(handle: In<RawVec>, exception: OutPtr<Exception>) -> usize {
-    let handle = try_not_null!(handle.as_ptr(), &exception, 0usize);
-    let handle = unsafe { &*handle.as_ptr() };
-    handle.len()
+    ffi_boundary!(&exception, 0usize, {
+        let handle = try_not_null!(handle.as_ptr(), &exception, 0usize);
+        let handle = unsafe { &*handle.as_ptr() };
+        handle.len()
+    })
 }
 
 #[no_mangle]
-pub extern "C" fn vec_push(mut handle: InOut<RawVec>, value: InRaw, exception: OutPtr<Exception>) {
-    let handle = unsafe { try_as_mut_ref!(handle, &exception, ()) };
-    handle.push(Arc::new(value));
+pub extern "C" fn vec_push(handle: InOut<RawVec>, value: InRaw, exception: OutPtr<Exception>) {
+    ffi_boundary!(&exception, (), {
+        with_vec(handle, &exception, |handle| handle.push(Arc::new(value)))
+    })
 }
 
+/// Boxes the `Arc`, as `Arc<RawValue>` is a fat pointer. Free with `vec_value_free`.
 macro_rules! vec_nullable {
     ($thing:expr) => {
-        match $thing {
-            Some(v) => {
-                let arc = $crate::sync::ArcPtr::from(v);
-                $crate::nullable::Nullable::new(&arc)
-            }
-            None => $crate::nullable::null(),
-        }
+        $crate::nullable::Nullable::from($thing)
     };
 }
 
@@ -46,9 +36,11 @@ macro_rules! vec_nullable {
 pub extern "C" fn vec_pop(
     mut handle: InOut<RawVec>,
     exception: OutPtr<Exception>,
-) -> Nullable<ArcPtr<RawValue>> {
-    let handle = unsafe { try_as_mut_ref!(handle, &exception) };
-    vec_nullable!(handle.pop())
+) -> Nullable<Arc<RawValue>> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_mut_ref!(handle, &exception) };
+        vec_nullable!(handle.pop())
+    })
 }
 
 #[no_mangle]
@@ -56,62 +48,364 @@ pub extern "C" fn vec_get(
     handle: In<RawVec>,
     index: u64,
     exception: OutPtr<Exception>,
-) -> Nullable<ArcPtr<RawValue>> {
-    let handle = unsafe { try_as_ref!(handle, &exception) };
-    vec_nullable!(handle.get(index as usize))
+) -> Nullable<Arc<RawValue>> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        vec_nullable!(handle.get(index as usize))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn vec_remove(
+    mut handle: InOut<RawVec>,
+    index: u64,
+    exception: OutPtr<Exception>,
+) -> Nullable<Arc<RawValue>> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_mut_ref!(handle, &exception) };
+        match handle.remove(index as usize) {
+            Ok(value) => vec_nullable!(Some(value)),
+            Err(e) => throw(e, &exception),
+        }
+    })
+}
+
+/// Removes the value at `index`, replacing it with the last value.
+#[no_mangle]
+pub extern "C" fn vec_swap_remove(
+    mut handle: InOut<RawVec>,
+    index: u64,
+    exception: OutPtr<Exception>,
+) -> Nullable<Arc<RawValue>> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_mut_ref!(handle, &exception) };
+        match handle.swap_remove(index as usize) {
+            Ok(value) => vec_nullable!(Some(value)),
+            Err(e) => throw(e, &exception),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn vec_clear(handle: InOut<RawVec>, exception: OutPtr<Exception>) {
+    ffi_boundary!(&exception, (), {
+        with_vec(handle, &exception, |handle| handle.clear())
+    })
+}
+
+/// Drops the values from `len` onwards, if there are any.
+#[no_mangle]
+pub extern "C" fn vec_truncate(handle: InOut<RawVec>, len: u64, exception: OutPtr<Exception>) {
+    ffi_boundary!(&exception, (), {
+        with_vec(handle, &exception, |handle| handle.truncate(len as usize))
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn vec_reserve(
+    handle: InOut<RawVec>,
+    additional: u64,
+    exception: OutPtr<Exception>,
+) {
+    ffi_boundary!(&exception, (), {
+        with_vec(handle, &exception, |handle| {
+            handle.reserve(additional as usize)
+        })
+    })
+}
+
+/// Appends the values of `other`, which must hold the same type, sharing rather than copying
+/// them.
+#[no_mangle]
+pub extern "C" fn vec_extend_from(
+    handle: InOut<RawVec>,
+    other: In<RawVec>,
+    exception: OutPtr<Exception>,
+) {
+    ffi_boundary!(&exception, (), {
+        // Copied before `handle` is borrowed mutably, as `other` may be the same vector.
+        let (ty, items) = match unsafe { other.as_ref() } {
+            Some(other) => (other.ty, other.snapshot()),
+            None => {
+                let _: Nullable<()> = throw_null("other", &exception);
+                return;
+            }
+        };
+        with_vec(handle, &exception, |handle| {
+            if handle.ty != ty {
+                let _: Nullable<()> = throw_kind(
+                    ExceptionKind::TypeMismatch,
+                    "vectors hold different types",
+                    &exception,
+                );
+                return;
+            }
+            handle.extend(items);
+        })
+    })
+}
+
+/// Borrows the vector for an export that returns nothing, throwing if `handle` is null.
+fn with_vec<F: FnOnce(&mut RawVec)>(
+    mut handle: InOut<RawVec>,
+    exception: &OutPtr<Exception>,
+    f: F,
+) {
+    match unsafe { handle.as_mut_ref() } {
+        Some(handle) => f(handle),
+        None => {
+            let _: Nullable<()> = throw_null("handle", exception);
+        }
+    }
+}
+
+fn throw_out_of_bounds(error: OutOfBounds, exception: &OutPtr<Exception>) {
+    let _: Nullable<()> = throw(error, exception);
+}
+
+// The functions below are called by `generate_vec_ffi!` once it has matched the type tag.
+
+/// Copies `*value` as a `T`, throwing if it is null or the vector holds other types.
+unsafe fn value<T: Clone + 'static>(
+    handle: &RawVec,
+    value: &InRaw,
+    exception: &OutPtr<Exception>,
+) -> Option<T> {
+    if !handle.holds::<T>() {
+        type_mismatch(exception);
+        return None;
+    }
+    match value.as_ptr() {
+        Some(value) => Some((*(value.as_ptr() as *const T)).clone()),
+        None => {
+            let _: Nullable<()> = throw_null("value", exception);
+            None
+        }
+    }
+}
+
+/// # Safety
+///
+/// `value` must be null or point to a valid `T`.
+#[doc(hidden)]
+pub unsafe fn insert<T: Clone + Send + Sync + 'static>(
+    handle: InOut<RawVec>,
+    index: u64,
+    value: &InRaw,
+    exception: &OutPtr<Exception>,
+) {
+    with_vec(handle, exception, |handle| {
+        if let Some(value) = self::value::<T>(handle, value, exception) {
+            if let Err(e) = handle.insert(index as usize, Arc::new(value)) {
+                throw_out_of_bounds(e, exception);
+            }
+        }
+    })
+}
+
+/// # Safety
+///
+/// `value` must be null or point to a valid `T`.
+#[doc(hidden)]
+pub unsafe fn set<T: Clone + Send + Sync + 'static>(
+    handle: InOut<RawVec>,
+    index: u64,
+    value: &InRaw,
+    exception: &OutPtr<Exception>,
+) {
+    with_vec(handle, exception, |handle| {
+        if let Some(value) = self::value::<T>(handle, value, exception) {
+            if let Err(e) = handle.set(index as usize, Arc::new(value)) {
+                throw_out_of_bounds(e, exception);
+            }
+        }
+    })
+}
+
+#[doc(hidden)]
+pub fn type_mismatch(exception: &OutPtr<Exception>) {
+    let _: Nullable<()> = throw_kind(
+        ExceptionKind::TypeMismatch,
+        "unknown type tag, or the vector holds other types",
+        exception,
+    );
+}
+
+/// Returns another handle to the same vector, which sees its changes. Free with `vec_free`.
+#[no_mangle]
+pub extern "C" fn vec_share(handle: In<RawVec>, exception: OutPtr<Exception>) -> Nullable<RawVec> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        Nullable::from_owned_raw(handle.clone().into_raw())
+    })
+}
+
+/// Returns a new vector with the same values, which does not see changes to the original. Free
+/// with `vec_free`.
+#[no_mangle]
+pub extern "C" fn vec_deep_clone(
+    handle: In<RawVec>,
+    exception: OutPtr<Exception>,
+) -> Nullable<RawVec> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        Nullable::from_owned_raw(handle.deep_clone().into_raw())
+    })
 }
 
+/// Creates an iterator over a snapshot of the vector, taking its lock once. Free with
+/// `vec_iter_free`.
+#[no_mangle]
+pub extern "C" fn vec_iter_new(
+    handle: In<RawVec>,
+    exception: OutPtr<Exception>,
+) -> Nullable<RawIter> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        Nullable::from(Box::new(handle.snapshot()))
+    })
+}
+
+/// Returns the next value, to be freed with `vec_value_free`, or null at the end.
+#[no_mangle]
+pub extern "C" fn vec_iter_next(
+    mut iter: InOut<RawIter>,
+    exception: OutPtr<Exception>,
+) -> Nullable<Arc<RawValue>> {
+    ffi_boundary!(&exception, {
+        let iter = unsafe { try_as_mut_ref!(iter, &exception) };
+        vec_nullable!(iter.next())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn vec_iter_free(iter: Nullable<RawIter>) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        unsafe { iter.into_box() };
+    })
+}
+
+/// Frees a value returned by `vec_pop`, `vec_get`, `vec_remove`, `vec_swap_remove` or
+/// `vec_iter_next`.
+#[no_mangle]
+pub extern "C" fn vec_value_free(value: Nullable<Arc<RawValue>>) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        unsafe { value.into_box() };
+    })
+}
+
+/// Generates the type-dependent vector exports for the given types.
+///
+/// Each `NAME => Type` pair exports a `TypeTag` constant `NAME`, derived from the name, which
+/// foreign code passes to `vec_new`, `vec_free`, `vec_insert`, `vec_set` and `vec_debug_print` to
+/// select the type. Values are copied in with `Clone`.
 #[macro_export]
 macro_rules! generate_vec_ffi {
     { $( $ty_name:ident => $ty:ty ),* } => {
         $(
             #[no_mangle]
-            pub static $ty_name: core::any::TypeId = core::any::TypeId::of::<$ty>();
+            pub static $ty_name: $crate::tag::TypeTag = $crate::tag::TypeTag::from_name(stringify!($ty_name));
         )*
 
         /// A constructor for `Vec` for the C FFI, accepting types provided from generated constants.
         #[no_mangle]
-        pub extern "C" fn vec_new(ty: core::any::TypeId) -> $crate::nullable::Nullable<core::ffi::c_void> {
-            log::debug!("{:?}", ty);
-            $(
-                if &ty == &$ty_name { return $crate::nullable::Nullable::new($crate::vec::Vec::<$ty>::new().into_raw() as *mut core::ffi::c_void) }
-            )*
-            $crate::nullable::null()
+        pub extern "C" fn vec_new(ty: $crate::tag::TypeTag) -> $crate::nullable::Nullable<$crate::vec::RawVec> {
+            $crate::ffi_boundary!(&$crate::inout::OutPtr::null(), {
+                $crate::log::debug!("{:?}", ty);
+                $(
+                    if ty == $ty_name {
+                        let vec = $crate::vec::Vec::<$ty>::new().into_raw();
+                        return $crate::nullable::Nullable::from_owned_raw(vec as *const $crate::vec::RawVec);
+                    }
+                )*
+                $crate::nullable::null()
+            })
         }
 
         /// A function to free vectors.
         #[no_mangle]
-        pub extern "C" fn vec_free(handle: *mut RawVec, ty: core::any::TypeId, exception: *mut Exception) {
-            log::debug!("{:?}", ty);
-            // TODO: check handle isn't null
-            if handle.is_null() {
-                return;
-            }
+        pub extern "C" fn vec_free(
+            handle: *mut $crate::vec::RawVec,
+            ty: $crate::tag::TypeTag,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) {
+            $crate::ffi_boundary!(&exception, (), {
+                $crate::log::debug!("{:?}", ty);
+                if handle.is_null() {
+                    let _: $crate::nullable::Nullable<()> = $crate::exception::throw_null("handle", &exception);
+                    return;
+                }
 
-            $(
-                if &ty == &$ty_name { unsafe { $crate::vec::Vec::<$ty>::from_raw(handle as *mut _); } }
-            )*
-            // TODO: handle exception
+                $(
+                    if ty == $ty_name {
+                        unsafe { $crate::vec::Vec::<$ty>::from_raw(handle as *mut _); }
+                        return;
+                    }
+                )*
+
+                let _: $crate::nullable::Nullable<()> = $crate::exception::throw_kind(
+                    $crate::exception::ExceptionKind::TypeMismatch,
+                    "unknown type tag",
+                    &exception,
+                );
+            })
         }
 
+        /// Inserts a copy of `*value` at `index`, which may be the length to append.
         #[no_mangle]
-        pub extern "C" fn vec_debug_print(handle: *mut RawVec, ty: core::any::TypeId) {
-            log::debug!("{:?}", ty);
-            // TODO: check handle isn't null
-            if handle.is_null() {
-                return;
-            }
+        pub extern "C" fn vec_insert(
+            handle: $crate::inout::InOut<$crate::vec::RawVec>,
+            ty: $crate::tag::TypeTag,
+            index: u64,
+            value: $crate::inout::InRaw,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) {
+            $crate::ffi_boundary!(&exception, (), {
+                $(
+                    if ty == $ty_name {
+                        unsafe { $crate::vec::ffi::insert::<$ty>(handle, index, &value, &exception) };
+                        return;
+                    }
+                )*
+                $crate::vec::ffi::type_mismatch(&exception);
+            })
+        }
 
-            $(
-                if &ty == &$ty_name {
-                    unsafe {
-                        let raw_vec = &*handle;
-                        let v = raw_vec.iter(|x| x.map(|x| core::mem::transmute::<_, &$ty>(x)).collect::<alloc::vec::Vec<_>>());
-                        log::debug!("{:?}", &v);
+        /// Replaces the value at `index` with a copy of `*value`, dropping the old value.
+        #[no_mangle]
+        pub extern "C" fn vec_set(
+            handle: $crate::inout::InOut<$crate::vec::RawVec>,
+            ty: $crate::tag::TypeTag,
+            index: u64,
+            value: $crate::inout::InRaw,
+            exception: $crate::inout::OutPtr<$crate::exception::Exception>,
+        ) {
+            $crate::ffi_boundary!(&exception, (), {
+                $(
+                    if ty == $ty_name {
+                        unsafe { $crate::vec::ffi::set::<$ty>(handle, index, &value, &exception) };
+                        return;
                     }
+                )*
+                $crate::vec::ffi::type_mismatch(&exception);
+            })
+        }
+
+        #[no_mangle]
+        pub extern "C" fn vec_debug_print(handle: *const $crate::vec::RawVec, ty: $crate::tag::TypeTag) {
+            $crate::ffi_boundary!(&$crate::inout::OutPtr::null(), (), {
+                $crate::log::debug!("{:?}", ty);
+                if handle.is_null() {
+                    return;
                 }
-            )*
-            // TODO: handle exception
+
+                $(
+                    if ty == $ty_name {
+                        let vec = unsafe { &*(handle as *const $crate::vec::Vec<$ty>) };
+                        $crate::log::debug!("{:?}", vec.to_vec());
+                    }
+                )*
+            })
         }
     };
 }
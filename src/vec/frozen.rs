@@ -0,0 +1,220 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec as RealVec};
+use core::{any::TypeId, marker::PhantomData};
+
+use super::{RawIter, RawValue, RawVec, Vec};
+use crate::{
+    exception::Exception,
+    inout::{In, OutPtr},
+    nullable::Nullable,
+};
+
+#[derive(Debug, Clone)]
+pub struct RawFrozenVec {
+    items: Arc<[Arc<RawValue>]>,
+    ty: TypeId,
+    #[cfg(feature = "leak-tracker")]
+    type_name: &'static str,
+}
+
+impl RawFrozenVec {
+    #[inline]
+    fn new(vec: &RawVec) -> RawFrozenVec {
+        RawFrozenVec {
+            items: Arc::from(vec.vec.read().as_slice()),
+            ty: vec.ty,
+            #[cfg(feature = "leak-tracker")]
+            type_name: vec.type_name,
+        }
+    }
+
+    /// Boxes the snapshot to pass to C, recording it with the leak tracker.
+    fn into_raw(self) -> *const RawFrozenVec {
+        #[cfg(feature = "leak-tracker")]
+        let type_name = self.type_name;
+        let ptr = Box::into_raw(Box::new(self)) as *const RawFrozenVec;
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "FrozenVec", type_name);
+        ptr
+    }
+
+    /// Reclaims a snapshot boxed by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` and must not have been reclaimed already.
+    unsafe fn from_raw(ptr: *const RawFrozenVec) -> RawFrozenVec {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr);
+        *Box::from_raw(ptr as *mut RawFrozenVec)
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    #[inline]
+    fn get(&self, index: usize) -> Option<Arc<RawValue>> {
+        self.items.get(index).cloned()
+    }
+
+    #[inline]
+    fn thaw(&self) -> RawVec {
+        RawVec {
+            vec: Arc::new(parking_lot::RwLock::new(self.items.to_vec())),
+            ty: self.ty,
+            #[cfg(feature = "leak-tracker")]
+            type_name: self.type_name,
+        }
+    }
+}
+
+/// An immutable snapshot of a `Vec`, which can be read from any thread without locking.
+///
+/// Clones share the same items, which is safe as they can never change.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct FrozenVec<T>(RawFrozenVec, PhantomData<T>);
+
+impl<T> Clone for FrozenVec<T> {
+    fn clone(&self) -> FrozenVec<T> {
+        FrozenVec(self.0.clone(), PhantomData)
+    }
+}
+
+impl<T: Send + Sync + 'static> FrozenVec<T> {
+    pub(super) fn from_raw_vec(vec: &RawVec) -> FrozenVec<T> {
+        FrozenVec(RawFrozenVec::new(vec), PhantomData)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Arc<T>> {
+        self.0.get(index).and_then(|v| v.downcast().ok())
+    }
+
+    pub fn iter(&self) -> super::Iter<T> {
+        let items: RealVec<_> = self.0.items.to_vec();
+        super::Iter(RawIter(items.into_iter()), PhantomData)
+    }
+
+    /// Returns a new, mutable `Vec` with the same items.
+    pub fn thaw(&self) -> Vec<T> {
+        Vec(self.0.thaw(), PhantomData)
+    }
+
+    pub fn into_raw(self) -> *const FrozenVec<T> {
+        self.0.into_raw() as *const FrozenVec<T>
+    }
+
+    /// Reclaims a snapshot previously returned by `into_raw` or `vec_snapshot`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live snapshot of `T`s from `into_raw` or `vec_snapshot`, which must
+    /// not be used again once reclaimed.
+    pub unsafe fn from_raw(ptr: *const FrozenVec<T>) -> FrozenVec<T> {
+        let raw_vec = RawFrozenVec::from_raw(ptr as *const RawFrozenVec);
+
+        #[cfg(debug_assertions)]
+        assert_eq!(raw_vec.ty, TypeId::of::<T>());
+
+        FrozenVec(raw_vec, PhantomData)
+    }
+}
+
+impl<T: Send + Sync + 'static> From<&Vec<T>> for FrozenVec<T> {
+    fn from(vec: &Vec<T>) -> FrozenVec<T> {
+        vec.snapshot()
+    }
+}
+
+/// Returns an immutable snapshot of the vector. Free with `frozen_vec_free`.
+#[no_mangle]
+pub extern "C" fn vec_snapshot(
+    handle: In<RawVec>,
+    exception: OutPtr<Exception>,
+) -> Nullable<RawFrozenVec> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        Nullable::from_owned_raw(RawFrozenVec::new(handle).into_raw())
+    })
+}
+
+/// Returns a new, mutable vector with the same values. Free with `vec_free`.
+#[no_mangle]
+pub extern "C" fn frozen_vec_thaw(
+    handle: In<RawFrozenVec>,
+    exception: OutPtr<Exception>,
+) -> Nullable<RawVec> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        Nullable::from_owned_raw(handle.thaw().into_raw())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn frozen_vec_len(handle: In<RawFrozenVec>, exception: OutPtr<Exception>) -> usize {
+    ffi_boundary!(&exception, 0, {
+        let handle = unsafe { try_as_ref!(handle, &exception, 0) };
+        handle.len()
+    })
+}
+
+/// Returns the value at `index`, to be freed with `vec_value_free`, or null if out of bounds.
+#[no_mangle]
+pub extern "C" fn frozen_vec_get(
+    handle: In<RawFrozenVec>,
+    index: u64,
+    exception: OutPtr<Exception>,
+) -> Nullable<Arc<RawValue>> {
+    ffi_boundary!(&exception, {
+        let handle = unsafe { try_as_ref!(handle, &exception) };
+        Nullable::from(handle.get(index as usize))
+    })
+}
+
+/// Frees a snapshot. Unlike `vec_free`, no type tag is needed, as its values are shared.
+#[no_mangle]
+pub extern "C" fn frozen_vec_free(handle: Nullable<RawFrozenVec>) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        if !handle.is_null() {
+            drop(unsafe { RawFrozenVec::from_raw(handle.into_raw()) });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ffi() {
+        let mut vec = Vec::from(alloc::vec![1u64, 2]);
+        let raw_vec = vec.share().into_raw();
+        let handle = In::from(unsafe { &*(raw_vec as *const RawVec) });
+
+        let frozen = vec_snapshot(handle, OutPtr::null()).into_raw();
+        vec.push(3);
+        let frozen_handle = || In::from(unsafe { &*frozen });
+        assert_eq!(frozen_vec_len(frozen_handle(), OutPtr::null()), 2);
+
+        let value = unsafe { frozen_vec_get(frozen_handle(), 1, OutPtr::null()).into_box() };
+        assert_eq!((**value.unwrap()).downcast_ref::<u64>(), Some(&2));
+        assert!(frozen_vec_get(frozen_handle(), 2, OutPtr::null()).is_null());
+
+        let thawed = frozen_vec_thaw(frozen_handle(), OutPtr::null()).into_raw();
+        let thawed = unsafe { Vec::<u64>::from_raw(thawed as *const _) };
+        frozen_vec_free(unsafe { Nullable::from_raw(frozen) });
+        assert_eq!(thawed.to_owned_vec(), [1, 2]);
+
+        let shared = unsafe { Vec::<u64>::from_raw(raw_vec) };
+        assert_eq!(shared.to_owned_vec(), [1, 2, 3]);
+    }
+}
@@ -0,0 +1,397 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec as RealVec};
+use core::{any::Any, ffi::c_void, fmt::Debug, marker::PhantomData, ptr::NonNull};
+use parking_lot::RwLock;
+
+use crate::{
+    exception::{throw_kind, Exception, ExceptionKind},
+    inout::{In, InRaw, InSlice, OutPtr, OutRaw, SliceError},
+    macros::throw_slice,
+    nullable::{self, Nullable},
+    tag::TypeTag,
+};
+
+/// Contiguous storage for a `PrimitiveVec`, erased so that it may be used without its type.
+trait Storage: Any + Debug + Send + Sync {
+    fn as_ptr(&self) -> *const c_void;
+    fn len(&self) -> usize;
+    fn elem_size(&self) -> usize;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Copy + Debug + Send + Sync + 'static> Storage for RealVec<T> {
+    fn as_ptr(&self) -> *const c_void {
+        RealVec::as_ptr(self) as *const c_void
+    }
+
+    fn len(&self) -> usize {
+        RealVec::len(self)
+    }
+
+    fn elem_size(&self) -> usize {
+        core::mem::size_of::<T>()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RawPrimitiveVec(Arc<RwLock<Box<dyn Storage>>>);
+
+impl RawPrimitiveVec {
+    #[inline]
+    fn len(&self) -> usize {
+        self.0.read().len()
+    }
+
+    /// Reclaims a handle boxed by `PrimitiveVec::into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `PrimitiveVec::into_raw` and must not have been reclaimed already.
+    unsafe fn from_raw(ptr: *const RawPrimitiveVec) -> RawPrimitiveVec {
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::untrack(ptr);
+        *Box::from_raw(ptr as *mut RawPrimitiveVec)
+    }
+}
+
+/// A vector of `Copy` values stored contiguously, so foreign code can read them in bulk.
+///
+/// Like `Vec`, clones share the same storage.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct PrimitiveVec<T>(RawPrimitiveVec, PhantomData<T>);
+
+impl<T> Clone for PrimitiveVec<T> {
+    fn clone(&self) -> PrimitiveVec<T> {
+        PrimitiveVec(self.0.clone(), PhantomData)
+    }
+}
+
+impl<T: Copy + Debug + Send + Sync + 'static> PrimitiveVec<T> {
+    pub fn new() -> PrimitiveVec<T> {
+        PrimitiveVec::from(RealVec::new())
+    }
+
+    #[inline]
+    fn read<F: FnOnce(&RealVec<T>) -> O, O>(&self, f: F) -> O {
+        let guard = self.0 .0.read();
+        f(guard.as_any().downcast_ref().expect("storage matches type"))
+    }
+
+    #[inline]
+    fn write<F: FnOnce(&mut RealVec<T>) -> O, O>(&mut self, f: F) -> O {
+        let mut guard = self.0 .0.write();
+        f(guard
+            .as_any_mut()
+            .downcast_mut()
+            .expect("storage matches type"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, item: T) {
+        self.write(|x| x.push(item))
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.write(|x| x.pop())
+    }
+
+    pub fn get(&self, index: usize) -> Option<T> {
+        self.read(|x| x.get(index).copied())
+    }
+
+    pub fn extend_from_slice(&mut self, items: &[T]) {
+        self.write(|x| x.extend_from_slice(items))
+    }
+
+    /// Copies as many values as fit into `buf`, returning the number copied.
+    pub fn copy_into(&self, buf: &mut [T]) -> usize {
+        self.read(|x| {
+            let len = x.len().min(buf.len());
+            buf[..len].copy_from_slice(&x[..len]);
+            len
+        })
+    }
+
+    pub fn to_vec(&self) -> RealVec<T> {
+        self.read(|x| x.clone())
+    }
+
+    pub fn into_raw(self) -> *const PrimitiveVec<T> {
+        let ptr = Box::into_raw(Box::new(self.0)) as *const _;
+        #[cfg(feature = "leak-tracker")]
+        crate::leaks::track(ptr, "PrimitiveVec", core::any::type_name::<T>());
+        ptr
+    }
+
+    /// Reclaims a vector previously returned by `into_raw`, or a `RawPrimitiveVec` from
+    /// `vec_from_buffer`, failing with a `TypeMismatch` exception if it does not hold `T`. The
+    /// vector is not reclaimed on failure.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a live vector from `into_raw` or `vec_from_buffer`, which must not be
+    /// used again once reclaimed.
+    pub unsafe fn from_raw(ptr: *const PrimitiveVec<T>) -> Result<PrimitiveVec<T>, Exception> {
+        let raw_vec = &*(ptr as *const RawPrimitiveVec);
+        if !raw_vec.0.read().as_any().is::<RealVec<T>>() {
+            return Err(Exception::new(
+                ExceptionKind::TypeMismatch,
+                "vector holds a different type",
+            ));
+        }
+        let raw_vec = RawPrimitiveVec::from_raw(ptr as *const RawPrimitiveVec);
+        Ok(PrimitiveVec(raw_vec, PhantomData))
+    }
+}
+
+impl<T: Copy + Debug + Send + Sync + 'static> Default for PrimitiveVec<T> {
+    fn default() -> PrimitiveVec<T> {
+        PrimitiveVec::new()
+    }
+}
+
+impl<T: Copy + Debug + Send + Sync + 'static> From<RealVec<T>> for PrimitiveVec<T> {
+    fn from(vec: RealVec<T>) -> PrimitiveVec<T> {
+        PrimitiveVec(
+            RawPrimitiveVec(Arc::new(RwLock::new(Box::new(vec)))),
+            PhantomData,
+        )
+    }
+}
+
+impl<T: Copy + Debug + Send + Sync + 'static> From<&[T]> for PrimitiveVec<T> {
+    fn from(slice: &[T]) -> PrimitiveVec<T> {
+        PrimitiveVec::from(RealVec::from(slice))
+    }
+}
+
+// Written out in full, rather than generated, so that cursed-header can see them.
+#[no_mangle]
+pub static PRIMITIVE_U8: TypeTag = TypeTag::from_name("PRIMITIVE_U8");
+#[no_mangle]
+pub static PRIMITIVE_I8: TypeTag = TypeTag::from_name("PRIMITIVE_I8");
+#[no_mangle]
+pub static PRIMITIVE_U16: TypeTag = TypeTag::from_name("PRIMITIVE_U16");
+#[no_mangle]
+pub static PRIMITIVE_I16: TypeTag = TypeTag::from_name("PRIMITIVE_I16");
+#[no_mangle]
+pub static PRIMITIVE_U32: TypeTag = TypeTag::from_name("PRIMITIVE_U32");
+#[no_mangle]
+pub static PRIMITIVE_I32: TypeTag = TypeTag::from_name("PRIMITIVE_I32");
+#[no_mangle]
+pub static PRIMITIVE_U64: TypeTag = TypeTag::from_name("PRIMITIVE_U64");
+#[no_mangle]
+pub static PRIMITIVE_I64: TypeTag = TypeTag::from_name("PRIMITIVE_I64");
+#[no_mangle]
+pub static PRIMITIVE_F32: TypeTag = TypeTag::from_name("PRIMITIVE_F32");
+#[no_mangle]
+pub static PRIMITIVE_F64: TypeTag = TypeTag::from_name("PRIMITIVE_F64");
+
+macro_rules! from_buffer {
+    ($ty:expr, $ptr:expr, $len:expr, { $( $tag:ident => $t:ty ),* }) => {{
+        let (ty, ptr, len) = ($ty, $ptr, $len);
+        $(
+            if ty == $tag {
+                let slice = InSlice::from_raw_parts(ptr as *const $t, len);
+                let vec = unsafe { slice.as_slice() }.map(PrimitiveVec::from);
+                Some(vec.map(|x| x.into_raw() as *const RawPrimitiveVec))
+            } else
+        )* {
+            None
+        }
+    }};
+}
+
+/// Copies the buffer into a boxed vector if `ty` is a primitive type tag, checking that it may be
+/// borrowed first.
+fn from_buffer(
+    ty: TypeTag,
+    ptr: *const c_void,
+    len: usize,
+) -> Option<Result<*const RawPrimitiveVec, SliceError>> {
+    from_buffer!(ty, ptr, len, {
+        PRIMITIVE_U8 => u8,
+        PRIMITIVE_I8 => i8,
+        PRIMITIVE_U16 => u16,
+        PRIMITIVE_I16 => i16,
+        PRIMITIVE_U32 => u32,
+        PRIMITIVE_I32 => i32,
+        PRIMITIVE_U64 => u64,
+        PRIMITIVE_I64 => i64,
+        PRIMITIVE_F32 => f32,
+        PRIMITIVE_F64 => f64
+    })
+}
+
+/// Copies `len` values of the primitive type `ty` from `ptr` into a new vector.
+#[no_mangle]
+pub extern "C" fn vec_from_buffer(
+    ty: TypeTag,
+    ptr: InRaw,
+    len: usize,
+    exception: OutPtr<Exception>,
+) -> Nullable<RawPrimitiveVec> {
+    ffi_boundary!(&exception, {
+        let ptr = ptr.as_ptr().map(|x| x.as_ptr() as *const c_void);
+        match from_buffer(ty, ptr.unwrap_or(core::ptr::null()), len) {
+            Some(Ok(vec)) => Nullable::from_owned_raw(vec),
+            Some(Err(e)) => {
+                throw_slice("ptr", e, &exception);
+                nullable::null()
+            }
+            None => throw_kind(
+                ExceptionKind::TypeMismatch,
+                "unknown primitive type tag",
+                &exception,
+            ),
+        }
+    })
+}
+
+/// Borrows the vector's contiguous storage.
+///
+/// The pointer is invalidated by any change to the vector, including from another thread, so
+/// prefer `vec_copy_into` for vectors that are shared.
+#[no_mangle]
+pub extern "C" fn vec_as_ptr(
+    handle: In<RawPrimitiveVec>,
+    exception: OutPtr<Exception>,
+) -> *const c_void {
+    ffi_boundary!(&exception, core::ptr::null(), {
+        let handle = unsafe { try_as_ref!(handle, &exception, core::ptr::null()) };
+        handle.0.read().as_ptr()
+    })
+}
+
+/// Copies up to `len` values into `buf`, returning the number copied.
+#[no_mangle]
+pub extern "C" fn vec_copy_into(
+    handle: In<RawPrimitiveVec>,
+    buf: OutRaw,
+    len: usize,
+    exception: OutPtr<Exception>,
+) -> usize {
+    ffi_boundary!(&exception, 0, {
+        let handle = unsafe { try_as_ref!(handle, &exception, 0) };
+        let buf = try_not_null!(buf.as_ptr(), &exception, 0);
+
+        let storage = handle.0.read();
+        let len = storage.len().min(len);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                storage.as_ptr() as *const u8,
+                buf.as_ptr() as *mut u8,
+                len * storage.elem_size(),
+            )
+        };
+        len
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn primitive_vec_len(
+    handle: In<RawPrimitiveVec>,
+    exception: OutPtr<Exception>,
+) -> usize {
+    ffi_boundary!(&exception, 0, {
+        let handle = unsafe { try_as_ref!(handle, &exception, 0) };
+        handle.len()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn primitive_vec_free(handle: *mut RawPrimitiveVec) {
+    ffi_boundary!(&OutPtr::null(), (), {
+        if let Some(handle) = NonNull::new(handle) {
+            drop(unsafe { RawPrimitiveVec::from_raw(handle.as_ptr()) });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inout::Out;
+
+    #[test]
+    fn typed() {
+        let mut vec = PrimitiveVec::new();
+        vec.extend_from_slice(&[1u64, 2, 3]);
+        vec.push(4);
+        assert_eq!(vec.len(), 4);
+        assert_eq!(vec.get(3), Some(4));
+        assert_eq!(vec.pop(), Some(4));
+
+        let mut buf = [0u64; 2];
+        assert_eq!(vec.copy_into(&mut buf), 2);
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(vec.to_vec(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn bulk_ffi() {
+        let input = [1.5f64, 2.5, 3.5];
+        let mut exception = core::ptr::null_mut();
+        let handle = vec_from_buffer(
+            PRIMITIVE_F64,
+            In::from(unsafe { &*(input.as_ptr() as *const c_void) }),
+            input.len(),
+            OutPtr::from(&mut exception),
+        );
+        let handle = handle.into_raw();
+        assert!(exception.is_null());
+
+        let data = vec_as_ptr(In::from(unsafe { &*handle }), OutPtr::null()) as *const f64;
+        assert_eq!(unsafe { core::slice::from_raw_parts(data, 3) }, &input);
+
+        let mut out = [0f64; 8];
+        let n = vec_copy_into(
+            In::from(unsafe { &*handle }),
+            Out::from(unsafe { &mut *(out.as_mut_ptr() as *mut c_void) }),
+            out.len(),
+            OutPtr::null(),
+        );
+        assert_eq!(n, 3);
+        assert_eq!(&out[..3], &input);
+
+        let e = unsafe { PrimitiveVec::<u64>::from_raw(handle as *const _) }.unwrap_err();
+        assert_eq!(e.kind(), ExceptionKind::TypeMismatch);
+        let vec = unsafe { PrimitiveVec::<f64>::from_raw(handle as *const _) }.unwrap();
+        assert_eq!(vec.to_vec(), input);
+    }
+
+    #[test]
+    fn bulk_ffi_errors() {
+        let mut exception = core::ptr::null_mut();
+        let handle = vec_from_buffer(PRIMITIVE_U8, In::null(), 4, OutPtr::from(&mut exception));
+        assert!(handle.is_null());
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::NullArgument);
+
+        let input = [0u8; 9];
+        let misaligned = In::from(unsafe { &*(input[1..].as_ptr() as *const c_void) });
+        let handle = vec_from_buffer(PRIMITIVE_U64, misaligned, 1, OutPtr::from(&mut exception));
+        assert!(handle.is_null());
+        let e = unsafe { Exception::from_raw(core::ptr::NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::InvalidArgument);
+
+        let empty = vec_from_buffer(PRIMITIVE_U8, In::null(), 0, OutPtr::null());
+        primitive_vec_free(empty.into_raw() as *mut _);
+    }
+}
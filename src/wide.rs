@@ -0,0 +1,178 @@
+use alloc::boxed::Box;
+use alloc::string::{FromUtf16Error, String};
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+/// A borrowed UTF-16 string, as passed by JVM, .NET and Windows callers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WideStr<'a>(&'a [u16]);
+
+impl<'a> WideStr<'a> {
+    pub fn new(units: &'a [u16]) -> WideStr<'a> {
+        WideStr(units)
+    }
+
+    /// Borrows a NUL-terminated string, not including the NUL.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned and point to a NUL-terminated string of `u16`s that is not changed
+    /// while borrowed for `'a`.
+    pub unsafe fn from_ptr(ptr: NonNull<u16>) -> WideStr<'a> {
+        let mut len = 0;
+        while *ptr.as_ptr().add(len) != 0 {
+            len += 1;
+        }
+        WideStr::from_raw_parts(ptr, len)
+    }
+
+    /// Borrows a string of `len` code units, which may contain NULs.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be aligned and point to `len` `u16`s that are not changed while borrowed for
+    /// `'a`.
+    pub unsafe fn from_raw_parts(ptr: NonNull<u16>, len: usize) -> WideStr<'a> {
+        WideStr(core::slice::from_raw_parts(ptr.as_ptr(), len))
+    }
+
+    pub fn as_slice(&self) -> &'a [u16] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Converts to UTF-8, failing on unpaired surrogates.
+    pub fn to_string(&self) -> Result<String, FromUtf16Error> {
+        String::from_utf16(self.0)
+    }
+
+    /// Converts to UTF-8, replacing unpaired surrogates with U+FFFD.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf16_lossy(self.0)
+    }
+}
+
+/// An owned UTF-16 string and its length in code units, for returning over the FFI. It is also
+/// NUL-terminated, which is not counted in `len`. Released with `cursed_wide_string_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct WideString {
+    pub ptr: *mut u16,
+    pub len: usize,
+}
+
+impl WideString {
+    pub fn as_wide_str(&self) -> WideStr<'_> {
+        match NonNull::new(self.ptr) {
+            Some(ptr) => unsafe { WideStr::from_raw_parts(ptr, self.len) },
+            None => WideStr(&[]),
+        }
+    }
+}
+
+impl Drop for WideString {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            let slice = core::ptr::slice_from_raw_parts_mut(self.ptr, self.len + 1);
+            drop(unsafe { Box::from_raw(slice) });
+        }
+    }
+}
+
+impl From<Vec<u16>> for WideString {
+    fn from(mut units: Vec<u16>) -> WideString {
+        let len = units.len();
+        units.push(0);
+        WideString {
+            ptr: Box::into_raw(units.into_boxed_slice()) as *mut u16,
+            len,
+        }
+    }
+}
+
+impl From<&str> for WideString {
+    fn from(string: &str) -> WideString {
+        WideString::from(string.encode_utf16().collect::<Vec<_>>())
+    }
+}
+
+impl From<WideStr<'_>> for WideString {
+    fn from(string: WideStr) -> WideString {
+        WideString::from(string.0.to_vec())
+    }
+}
+
+/// Frees a string returned as a `WideString`.
+#[no_mangle]
+pub extern "C" fn cursed_wide_string_free(string: WideString) {
+    drop(string);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception::{Exception, ExceptionKind};
+    use crate::inout::{In, OutPtr};
+
+    fn units(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(core::iter::once(0)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let owned = WideString::from("héllo, 世界 🦀");
+        assert_eq!(unsafe { *owned.ptr.add(owned.len) }, 0);
+        assert_eq!(owned.as_wide_str().to_string().unwrap(), "héllo, 世界 🦀");
+
+        let input = units("héllo");
+        let borrowed = unsafe { WideStr::from_ptr(NonNull::from(&input[0])) };
+        assert_eq!(borrowed.len(), 5);
+        assert_eq!(borrowed.to_string().unwrap(), "héllo");
+        cursed_wide_string_free(WideString::from(borrowed));
+    }
+
+    fn read(string: In<u16>, len: usize, exception: OutPtr<Exception>) -> Option<String> {
+        let string = try_as_wide_str!(string, len = len, &exception, None);
+        Some(string)
+    }
+
+    fn read_nul(string: In<u16>, exception: OutPtr<Exception>) -> Option<String> {
+        let string = try_as_wide_str!(string, &exception, None);
+        Some(string)
+    }
+
+    #[test]
+    fn macro_variants() {
+        let input = [b'a' as u16, 0, b'b' as u16];
+        assert_eq!(
+            read(In::from(&input[0]), 3, OutPtr::null()).unwrap(),
+            "a\0b"
+        );
+        assert_eq!(read(In::null(), 0, OutPtr::null()).unwrap(), "");
+        assert_eq!(read_nul(In::from(&input[0]), OutPtr::null()).unwrap(), "a");
+
+        let mut exception = core::ptr::null_mut();
+        assert!(read_nul(In::null(), OutPtr::from(&mut exception)).is_none());
+        let e = unsafe { Exception::from_raw(NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::NullArgument);
+    }
+
+    #[test]
+    fn unpaired_surrogate() {
+        let input = [0xd800u16, b'x' as u16, 0];
+        let mut exception = core::ptr::null_mut();
+        assert!(read_nul(In::from(&input[0]), OutPtr::from(&mut exception)).is_none());
+        let e = unsafe { Exception::from_raw(NonNull::new(exception).unwrap()) };
+        assert_eq!(e.kind(), ExceptionKind::InvalidUtf16);
+
+        let lossy = WideStr::new(&input[..2]).to_string_lossy();
+        assert_eq!(lossy, "\u{fffd}x");
+    }
+}
@@ -0,0 +1,63 @@
+//! Checks that strings are freed with the layout they were allocated with, which needs its own
+//! `#[global_allocator]` and so its own test binary.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use std::alloc::System;
+
+use cursed::c_char::{
+    cursed_arc_c_str_drop, cursed_string_buf_free, cursed_string_free, ArcCStr, StringExt,
+};
+
+std::thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Counts the bytes allocated and not yet freed by the current thread, so a free with a
+/// different layout than its allocation shows up as a leak or an over-release.
+struct Tracking;
+
+unsafe impl GlobalAlloc for Tracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = LIVE.try_with(|x| x.set(x.get() + layout.size() as isize));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = LIVE.try_with(|x| x.set(x.get() - layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Tracking = Tracking;
+
+/// Returns the bytes left allocated by a second run of `f`, as the first may allocate state that
+/// lives on, such as the leak tracker's map of live objects.
+fn live_after<F: Fn()>(f: F) -> isize {
+    f();
+    let before = LIVE.with(Cell::get);
+    f();
+    LIVE.with(Cell::get) - before
+}
+
+#[test]
+fn layouts_match() {
+    let freed = live_after(|| {
+        let s = String::from("this is a sample").into_c_char().unwrap();
+        cursed_string_free(s.into_raw());
+    });
+    assert_eq!(freed, 0);
+
+    let freed = live_after(|| {
+        let buf = String::from("this is a\0 sample").into_c_char_buf();
+        cursed_string_buf_free(buf);
+    });
+    assert_eq!(freed, 0);
+
+    let freed = live_after(|| {
+        let s = ArcCStr::new("this is a sample").unwrap();
+        cursed_arc_c_str_drop(s.into_raw());
+    });
+    assert_eq!(freed, 0);
+}